@@ -0,0 +1,49 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use starfish::*;
+
+/// STEPS is how many instructions each benchmark executes.
+const STEPS: u64 = 100_000;
+
+/// SCRIPTS are the programs benchmarked: the animation from scripts/, and a tight counting loop.
+const SCRIPTS: &[(&str, &str)] = &[
+    ("waves", include_str!("../scripts/waves.sf")),
+    ("count", "0v\n >1+:a9*9*)?v\n ^          <\n;n<\n"),
+];
+
+fn codebox(script: &str, trace_cache: bool) -> CodeBox {
+    CodeBox::builder(script)
+        .io(MemoryIo::default())
+        .trace_cache(trace_cache)
+        .build()
+}
+
+fn bench(c: &mut Criterion) {
+    let mut group = c.benchmark_group("swim");
+    for (name, script) in SCRIPTS {
+        // Swimming one instruction at a time, as the debugger and visualizer do.
+        group.bench_with_input(BenchmarkId::new("swim", name), script, |b, script| {
+            b.iter(|| {
+                let mut codebox = codebox(script, false);
+                for _ in 0..STEPS {
+                    if !matches!(codebox.swim(), Ok(Step::Continue | Step::Output(_))) {
+                        break;
+                    }
+                }
+            })
+        });
+        for (id, trace_cache) in [("run", false), ("run_traced", true)] {
+            group.bench_with_input(BenchmarkId::new(id, name), script, |b, script| {
+                b.iter(|| {
+                    codebox(script, trace_cache).run(Limits {
+                        max_steps: Some(STEPS),
+                        ..Default::default()
+                    })
+                })
+            });
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench);
+criterion_main!(benches);
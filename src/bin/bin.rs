@@ -1,63 +1,487 @@
-use clap::Parser;
+use chrono::NaiveTime;
+use clap::{Args as ClapArgs, Parser, Subcommand};
 use starfish::*;
-use std::{fs, thread, time};
+use std::io::{self, IsTerminal, Read, Write};
+use std::{fs, process, thread, time};
+
+mod dap;
+mod debug;
+mod lsp;
+mod rpc;
+mod trace;
+mod tui;
 
 #[derive(Parser, Debug)]
-#[clap(version, about, long_about = None)]
+#[clap(
+    version,
+    about,
+    long_about = None,
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
+)]
 struct Args {
+    #[clap(subcommand)]
+    command: Option<Command>,
+
     /// Path to *><> script
-    #[clap()]
-    path: String,
+    #[clap(required = true)]
+    path: Option<String>,
 
-    /// Initial stack (example: --stack "10 'olleh'")
-    #[clap(short = 's', long)]
-    stack: Option<String>,
+    #[clap(flatten)]
+    options: CodeBoxOptions,
 
-    /// Output stack each tick
+    /// Output stack each tick, to stderr
     #[clap(short = 'S', long = "output-stack")]
     output_stack: bool,
 
-    /// Output codebox each tick
+    /// Show the codebox, stacks and output in a full-screen visualizer (or output the codebox each tick to stderr
+    /// when stdout isn't a terminal)
     #[clap(short = 'c', long = "output-codebox")]
     output_codebox: bool,
 
     /// Delay between each tick in milliseconds
     #[clap(short = 'd', long = "delay", default_value_t = 0)]
     delay: u64,
+
+    /// Write a JSON record of every step to this file
+    #[clap(long, value_name = "FILE")]
+    trace: Option<String>,
+
+    /// Only trace these instructions (example: --trace-only "[]CR")
+    #[clap(long, value_name = "INSTRUCTIONS", requires = "trace")]
+    trace_only: Option<String>,
+
+    /// Only trace steps in this range, counting from 0 (example: --trace-steps 100..200)
+    #[clap(long, value_name = "START..END", requires = "trace")]
+    trace_steps: Option<trace::StepRange>,
+
+    /// Only trace steps which change the current stack
+    #[clap(long, requires = "trace")]
+    trace_stack_changes: bool,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Serve the Debug Adapter Protocol over stdin and stdout, for editors
+    Dap {
+        #[clap(flatten)]
+        options: CodeBoxOptions,
+    },
+    /// Step through a *><> script interactively
+    Debug {
+        /// Path to *><> script
+        #[clap()]
+        path: String,
+
+        #[clap(flatten)]
+        options: CodeBoxOptions,
+    },
+    /// Annotate every reachable cell of a *><> script, or narrate running it
+    Explain {
+        /// Path to *><> script
+        #[clap()]
+        path: String,
+
+        /// Instead, run this many steps, saying what each one did. Input is read from stdin, and output is
+        /// narrated rather than written
+        #[clap(long, value_name = "N")]
+        narrate: Option<u64>,
+
+        #[clap(flatten)]
+        options: CodeBoxOptions,
+    },
+    /// Check a *><> script for problems without running it
+    Lint {
+        /// Path to *><> script
+        #[clap()]
+        path: String,
+
+        /// Output the diagnostics as JSON
+        #[clap(long)]
+        json: bool,
+
+        #[clap(flatten)]
+        options: CodeBoxOptions,
+    },
+    /// Serve the Language Server Protocol over stdin and stdout, for editors
+    Lsp {
+        #[clap(flatten)]
+        options: CodeBoxOptions,
+    },
+    /// Run a *><> script, then show how many times each cell was executed
+    Profile {
+        /// Path to *><> script
+        #[clap()]
+        path: String,
+
+        /// Stop after this many instructions
+        #[clap(long)]
+        max_steps: Option<u64>,
+
+        /// Write the heatmap and counts to an HTML page
+        #[clap(long, value_name = "FILE")]
+        html: Option<String>,
+
+        /// Write the heatmap to an SVG image
+        #[clap(long, value_name = "FILE")]
+        svg: Option<String>,
+
+        /// Write the raw counts for each cell and direction to a CSV file
+        #[clap(long, value_name = "FILE")]
+        csv: Option<String>,
+
+        #[clap(flatten)]
+        options: CodeBoxOptions,
+    },
+}
+
+/// CodeBoxOptions are the options shared by every way of running a script.
+#[derive(ClapArgs, Debug, Clone)]
+pub struct CodeBoxOptions {
+    /// Initial stack (example: --stack "10 'olleh'")
+    #[clap(short = 's', long)]
+    stack: Option<String>,
+
+    /// Language dialect: fish, starfish or fishinterpreter.com
+    #[clap(long, default_value_t = Dialect::Starfish)]
+    dialect: Dialect,
+
+    /// How numbers are represented: float, integer, rational or bigint
+    #[clap(long, default_value_t = NumericMode::Float)]
+    numeric_mode: NumericMode,
+
+    /// How text maps to values: byte, or codepoint to treat the script, input and output as UTF-8 characters
+    #[clap(long, default_value_t = Encoding::Byte)]
+    encoding: Encoding,
+
+    /// Seed for the directions chosen by "x", to make runs reproducible
+    #[clap(long)]
+    seed: Option<u64>,
+
+    /// Start a virtual clock at this time of day (example: --fake-time 12:34:56). It only moves forward when
+    /// sleeping, which happens instantly
+    #[clap(long, value_name = "HH:MM:SS")]
+    fake_time: Option<NaiveTime>,
+
+    /// Confine the files "F" can use to this directory
+    #[clap(long, value_name = "DIR")]
+    fs_root: Option<String>,
+
+    /// Don't let "F" use any files
+    #[clap(long, conflicts_with = "fs-root")]
+    no_fs: bool,
+}
+
+impl CodeBoxOptions {
+    /// builder returns a CodeBoxBuilder for script configured by the options, exiting if they're invalid.
+    pub fn builder<'a>(&self, script: &'a str) -> CodeBoxBuilder<'a> {
+        let stack = match &self.stack {
+            None => Stack::new(None),
+            Some(v) => Stack::from_string(v).unwrap_or_else(|e| {
+                eprintln!("invalid initial stack: {}", e);
+                process::exit(2);
+            }),
+        };
+        let mut builder = CodeBox::builder(script)
+            .stack(stack)
+            .dialect(self.dialect)
+            .numeric_mode(self.numeric_mode)
+            .encoding(self.encoding);
+        if let Some(seed) = self.seed {
+            builder = builder.seed(seed);
+        }
+        if let Some(time) = self.fake_time {
+            builder = builder.clock(Clock::Virtual(time));
+        }
+        if let Some(root) = &self.fs_root {
+            let fs = RootFs::new(root).unwrap_or_else(|e| {
+                eprintln!("invalid file system root {}: {}", root, e);
+                process::exit(2);
+            });
+            builder = builder.file_system(fs);
+        }
+        if self.no_fs {
+            builder = builder.file_system(DenyFs);
+        }
+        builder
+    }
+}
+
+/// read_script reads the script at path, exiting if it can't.
+fn read_script(path: &str) -> String {
+    fs::read_to_string(path).unwrap_or_else(|e| {
+        eprintln!("couldn't read {}: {}", path, e);
+        process::exit(2);
+    })
 }
 
 pub fn main() {
     let args = Args::parse();
-    let stack: Stack;
-    match args.stack {
-        None => stack = Stack::new(None),
-        Some(v) => stack = Stack::from_string(&v).unwrap(),
+    match args.command {
+        Some(Command::Dap { options }) => dap::dap(options),
+        Some(Command::Debug { path, options }) => debug::debug(read_script(&path), options),
+        Some(Command::Explain {
+            path,
+            narrate: None,
+            options,
+        }) => explain(&path, &options),
+        Some(Command::Explain {
+            path,
+            narrate: Some(steps),
+            options,
+        }) => narrate(&path, steps, &options),
+        Some(Command::Lint {
+            path,
+            json,
+            options,
+        }) => lint(&path, json, &options),
+        Some(Command::Lsp { options }) => lsp::lsp(options),
+        Some(Command::Profile {
+            path,
+            max_steps,
+            html,
+            svg,
+            csv,
+            options,
+        }) => profile(&path, max_steps, [html, svg, csv], &options),
+        None => run(args),
+    }
+}
+
+/// explain prints the script at path row by row, each followed by annotations for the reachable cells in it.
+fn explain(path: &str, options: &CodeBoxOptions) {
+    let script = read_script(path);
+    let codebox = options.builder(&script).io(MemoryIo::default()).build();
+    let explanation = codebox.explain();
+    let mut annotations = explanation.annotations.iter().peekable();
+    let (x0, y0) = codebox.origin();
+    let (width, height) = codebox.size();
+    for y in y0..y0 + height as i64 {
+        let row: String = (x0..x0 + width as i64)
+            .map(|x| codebox.cell_char(x, y))
+            .collect();
+        println!("{:>4} | {}", y, row.trim_end());
+        while let Some(a) = annotations.next_if(|a| a.position().1 == y) {
+            let (x, y) = a.position();
+            let mut line = format!("     |   ({}, {}) {}", x, y, a);
+            if let Annotation::Instruction { instruction, .. } = a {
+                if !codebox.dialect().has(*instruction) {
+                    line += &format!(" (not in the {} dialect)", codebox.dialect());
+                }
+            }
+            println!("{}", line);
+        }
+    }
+    if !explanation.complete {
+        println!("some jumps go to coordinates worked out while running, so not every reachable cell is annotated");
+    }
+}
+
+/// narrate runs the script at path for up to steps steps, printing a sentence for each.
+fn narrate(path: &str, steps: u64, options: &CodeBoxOptions) {
+    let script = read_script(path);
+    let mut input = Vec::new();
+    if !io::stdin().is_terminal() {
+        if let Err(e) = io::stdin().read_to_end(&mut input) {
+            eprintln!("couldn't read input: {}", e);
+            process::exit(2);
+        }
+    }
+    let mut codebox = options.builder(&script).io(MemoryIo::new(&input)).build();
+    for step in 0..steps {
+        let (x, y) = codebox.position();
+        let c = codebox.cell_char(x, y);
+        match codebox.narrate() {
+            Ok((result, sentence)) => {
+                println!("{:>6}  ({}, {}) {:?}: {}", step, x, y, c, sentence);
+                if result == Step::Halt {
+                    return;
+                }
+            }
+            Err(e) => {
+                println!("{:>6}  ({}, {}) {:?}: failed: {}", step, x, y, c, e);
+                process::exit(1);
+            }
+        }
+    }
+    println!("stopped after {} steps", steps);
+}
+
+/// lint prints the diagnostics for the script at path, exiting with 1 if any are errors.
+fn lint(path: &str, json: bool, options: &CodeBoxOptions) {
+    let script = read_script(path);
+    let codebox = options.builder(&script).io(MemoryIo::default()).build();
+    let diagnostics = codebox.lint();
+    if json {
+        println!("{}", serde_json::to_string_pretty(&diagnostics).unwrap());
+    } else {
+        for d in &diagnostics {
+            println!("{} ({}, {}): {}: {}", path, d.x, d.y, d.severity, d.message);
+        }
+        let count = |severity| {
+            diagnostics
+                .iter()
+                .filter(|d| d.severity == severity)
+                .count()
+        };
+        println!(
+            "{} errors, {} warnings, {} infos",
+            count(Severity::Error),
+            count(Severity::Warning),
+            count(Severity::Info)
+        );
+    }
+    if diagnostics.iter().any(|d| d.severity == Severity::Error) {
+        process::exit(1);
+    }
+}
+
+/// profile runs the script at path, then prints a heatmap and totals to stderr, so they don't mix with the ><>'s
+/// output. files are where to write the HTML, SVG and CSV reports, if anywhere.
+fn profile(
+    path: &str,
+    max_steps: Option<u64>,
+    files: [Option<String>; 3],
+    options: &CodeBoxOptions,
+) {
+    let script = read_script(path);
+    let mut codebox = options.builder(&script).build();
+    let limits = Limits {
+        max_steps,
+        ..Limits::default()
+    };
+    let (report, profile) = codebox.profile(limits);
+    _ = io::stdout().flush();
+
+    let mut stderr = io::stderr().lock();
+    _ = writeln!(stderr);
+    _ = write!(stderr, "{}", profile.ansi(&codebox));
+    _ = writeln!(
+        stderr,
+        "{} steps, at most {} values on a stack and {} stacks",
+        profile.steps, profile.max_depth, profile.max_stacks
+    );
+    for (r, n) in profile.instructions_by_count().into_iter().take(10) {
+        _ = writeln!(stderr, "{:>12}  {:?}", n, r);
+    }
+    match &report.exit {
+        ExitReason::Halted => {}
+        ExitReason::StepLimit => _ = writeln!(stderr, "stopped after {} steps", report.steps),
+        ExitReason::Error(e) => _ = writeln!(stderr, "{}", e),
+        reason => _ = writeln!(stderr, "stopped: {:?}", reason),
+    }
+
+    let [html, svg, csv] = files;
+    let write = |file: Option<String>, render: fn(&Profile, &CodeBox) -> String| {
+        if let Some(file) = file {
+            if let Err(e) = fs::write(&file, render(&profile, &codebox)) {
+                eprintln!("couldn't write {}: {}", file, e);
+                process::exit(2);
+            }
+        }
+    };
+    write(html, Profile::html);
+    write(svg, Profile::svg);
+    write(csv, Profile::csv);
+    if let ExitReason::Error(_) = report.exit {
+        process::exit(1);
+    }
+}
+
+/// RUN_CHUNK is how many steps swim_fast runs at a time, so the output CodeBox::run collects for its report
+/// doesn't grow without bound.
+const RUN_CHUNK: u64 = 1 << 20;
+
+/// swim_fast runs codebox with CodeBox::run until the ><> halts, exiting if it fails.
+fn swim_fast(codebox: &mut CodeBox) {
+    loop {
+        let report = codebox.run(Limits {
+            max_steps: Some(RUN_CHUNK),
+            ..Limits::default()
+        });
+        match report.exit {
+            ExitReason::Halted => return,
+            ExitReason::Error(e) => {
+                _ = io::stdout().flush();
+                eprintln!("{}", e);
+                eprintln!("something smells fishy...");
+                process::exit(1);
+            }
+            _ => {}
+        }
     }
-    let mut codebox = CodeBox::new(&fs::read_to_string(args.path).unwrap(), stack, false);
+}
 
-    let mut end = false;
-    let mut output: Option<String>;
-    let mut sleep_ms: f64;
+/// run swims through the script given on the command line, printing whatever was asked for each tick.
+fn run(args: Args) {
+    let script = read_script(&args.path.unwrap());
+    if args.output_codebox && io::stdout().is_terminal() {
+        if let Err(e) = tui::visualize(&script, &args.options, args.delay) {
+            eprintln!("visualizer failed: {}", e);
+            process::exit(1);
+        }
+        return;
+    }
+    let mut codebox = args.options.builder(&script).build();
+    let mut trace = args.trace.as_ref().map(|path| {
+        let filter = trace::TraceFilter {
+            instructions: args.trace_only.as_ref().map(|s| s.chars().collect()),
+            steps: args.trace_steps.clone(),
+            stack_changes: args.trace_stack_changes,
+        };
+        trace::TraceLog::create(path, filter).unwrap_or_else(|e| {
+            eprintln!("couldn't create {}: {}", path, e);
+            process::exit(2);
+        })
+    });
+
+    // Without anything to do between steps, the ><> can swim through CodeBox::run's cache of decoded traces.
+    if !args.output_codebox && !args.output_stack && trace.is_none() && args.delay == 0 {
+        swim_fast(&mut codebox);
+        return;
+    }
 
-    while !end {
+    // Everything but the ><>'s own output goes to stderr, so stdout can be piped.
+    let mut stderr = io::stderr();
+    let code = loop {
         if args.output_codebox {
-            codebox.print(false);
+            _ = codebox.write_codebox(&mut stderr, false);
         }
         if args.output_stack {
-            println!("Stack: {}", codebox.string_stack());
+            _ = writeln!(stderr, "Stack: {}", codebox.string_stack());
         }
 
-        (output, end, sleep_ms) = codebox.swim();
-        match output {
-            Some(val) => print!("{}", val),
-            None => {}
+        let before = trace.as_ref().and_then(|trace| trace.before(&codebox));
+        let result = codebox.swim();
+        if let Some(trace) = &mut trace {
+            if let Err(e) = trace.after(before, &codebox, &result) {
+                eprintln!("couldn't write trace: {}", e);
+                process::exit(2);
+            }
         }
-
-        if sleep_ms > 0.0 {
-            thread::sleep(time::Duration::from_millis(sleep_ms as u64));
+        match result {
+            Ok(Step::Halt) => break 0,
+            Ok(_) => {}
+            Err(e) => {
+                _ = io::stdout().flush();
+                eprintln!("{}", e);
+                eprintln!("something smells fishy...");
+                break 1;
+            }
         }
+
         if args.delay > 0 {
             thread::sleep(time::Duration::from_millis(args.delay));
         }
+    };
+
+    if let Some(trace) = trace {
+        if let Err(e) = trace.finish() {
+            eprintln!("couldn't write trace: {}", e);
+            process::exit(2);
+        }
+    }
+    if code != 0 {
+        process::exit(code);
     }
 }
@@ -0,0 +1,490 @@
+use crate::rpc::{read_message, write_message};
+use crate::CodeBoxOptions;
+use serde_json::{json, Value as Json};
+use starfish::*;
+use std::fs;
+use std::io::{self, Write};
+use std::process;
+use std::sync::mpsc::{self, TryRecvError};
+use std::thread;
+
+/// THREAD is the id of the only thread, the ><>.
+const THREAD: i64 = 1;
+
+/// STEPS_PER_CHECK is how many instructions run between checks for requests, like a pause, from the client.
+const STEPS_PER_CHECK: u64 = 10_000;
+
+/// STATE and STACKS are the variables references of the scopes. Stack i is STACKS + 1 + i.
+const STATE: i64 = 1;
+const STACKS: i64 = 2;
+
+/// Run is how far the ><> swims before stopping again.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Run {
+    /// Stopped waits for the client.
+    Stopped,
+    /// Continue swims until a breakpoint, a pause, a halt or an error.
+    Continue,
+    /// Until swims until at most this many calls are in progress, which is how next steps over calls and stepOut
+    /// leaves one. stepIn stops after one instruction, as every depth is at most usize::MAX.
+    Until(usize),
+}
+
+/// Session is a launched script.
+struct Session {
+    codebox: CodeBox,
+    path: String,
+    /// calls are the cells of the "C"s which made the calls in progress, outermost first.
+    calls: Vec<(i64, i64)>,
+    run: Run,
+    /// resumed is set when the ><> starts swimming again, so a breakpoint it's stopped on doesn't stop it at once.
+    resumed: bool,
+    /// failed is set after an error, so resuming ends the session.
+    failed: bool,
+}
+
+/// Breakpoint stops the ><> before it executes a cell in row y, or only the cell at x, y if x is given.
+struct Breakpoint {
+    x: Option<i64>,
+    y: i64,
+}
+
+/// Adapter is a debug adapter for one client, speaking over stdin and stdout.
+struct Adapter {
+    options: CodeBoxOptions,
+    out: io::Stdout,
+    seq: i64,
+    /// line_base and column_base are what the client numbers the first row and column of the codebox.
+    line_base: i64,
+    column_base: i64,
+    breakpoints: Vec<Breakpoint>,
+    session: Option<Session>,
+    stop_on_entry: bool,
+    configured: bool,
+    done: bool,
+}
+
+impl Adapter {
+    fn send(&mut self, mut message: Json) {
+        self.seq += 1;
+        message["seq"] = json!(self.seq);
+        if let Err(e) = write_message(&mut self.out.lock(), &message) {
+            eprintln!("couldn't write to the client: {}", e);
+            process::exit(1);
+        }
+    }
+
+    fn event(&mut self, event: &str, body: Json) {
+        self.send(json!({ "type": "event", "event": event, "body": body }));
+    }
+
+    fn stopped(&mut self, reason: &str, text: Option<String>) {
+        if let Some(session) = &mut self.session {
+            session.run = Run::Stopped;
+        }
+        self.event(
+            "stopped",
+            json!({ "reason": reason, "threadId": THREAD, "allThreadsStopped": true, "text": text }),
+        );
+    }
+
+    /// end tells the client the script has finished with code, and forgets it.
+    fn end(&mut self, code: i32) {
+        self.session = None;
+        self.event("exited", json!({ "exitCode": code }));
+        self.event("terminated", json!({}));
+    }
+
+    /// start stops on entry or starts swimming, once the script is launched and the client has sent its breakpoints.
+    fn start(&mut self) {
+        if !self.configured || self.session.is_none() {
+            return;
+        }
+        if self.stop_on_entry {
+            self.stopped("entry", None);
+        } else {
+            self.resume(Run::Continue);
+        }
+    }
+
+    fn resume(&mut self, run: Run) {
+        let Some(session) = &mut self.session else {
+            return;
+        };
+        if session.failed {
+            self.end(1);
+            return;
+        }
+        session.run = run;
+        session.resumed = true;
+    }
+
+    fn at_breakpoint(&self, session: &Session) -> bool {
+        let (x, y) = session.codebox.position();
+        self.breakpoints
+            .iter()
+            .any(|b| b.y == y && b.x.is_none_or(|bx| bx == x))
+    }
+
+    /// swim runs the ><> for up to STEPS_PER_CHECK instructions, or until it stops.
+    fn swim(&mut self) {
+        for _ in 0..STEPS_PER_CHECK {
+            let Some(session) = &self.session else {
+                return;
+            };
+            if session.run == Run::Stopped {
+                return;
+            }
+            if !session.resumed && self.at_breakpoint(session) {
+                self.stopped("breakpoint", None);
+                return;
+            }
+
+            let session = self.session.as_mut().unwrap();
+            session.resumed = false;
+            let codebox = &mut session.codebox;
+            let (x, y) = codebox.position();
+            let instruction = if codebox.string_mode().is_none() && !codebox.deep_sea() {
+                codebox.cell_char(x, y)
+            } else {
+                ' '
+            };
+            match codebox.swim() {
+                Ok(step) => {
+                    match instruction {
+                        'C' => session.calls.push((x, y)),
+                        'R' => _ = session.calls.pop(),
+                        _ => {}
+                    }
+                    let depth = session.calls.len();
+                    match step {
+                        Step::Output(s) => {
+                            self.event("output", json!({ "category": "stdout", "output": s }))
+                        }
+                        Step::Halt => {
+                            self.end(0);
+                            return;
+                        }
+                        Step::Continue | Step::Sleep(_) => {}
+                    }
+                    if let Some(Session {
+                        run: Run::Until(until),
+                        ..
+                    }) = self.session
+                    {
+                        if depth <= until {
+                            self.stopped("step", None);
+                            return;
+                        }
+                    }
+                }
+                Err(e) => {
+                    session.failed = true;
+                    self.event(
+                        "output",
+                        json!({ "category": "stderr", "output": format!("{}\n", e) }),
+                    );
+                    self.stopped("exception", Some(e.to_string()));
+                    return;
+                }
+            }
+        }
+    }
+
+    fn session(&self) -> Result<&Session, String> {
+        self.session
+            .as_ref()
+            .ok_or_else(|| String::from("no script is running"))
+    }
+
+    fn launch(&mut self, arguments: &Json) -> Result<Json, String> {
+        let path = arguments["program"]
+            .as_str()
+            .ok_or("expected a program to launch")?;
+        let script =
+            fs::read_to_string(path).map_err(|e| format!("couldn't read {}: {}", path, e))?;
+        let input = arguments["input"].as_str().unwrap_or_default();
+        let codebox = self
+            .options
+            .builder(&script)
+            .io(MemoryIo::new(input.as_bytes()))
+            .build();
+        self.stop_on_entry = arguments["stopOnEntry"].as_bool().unwrap_or(false);
+        self.session = Some(Session {
+            codebox,
+            path: path.to_string(),
+            calls: Vec::new(),
+            run: Run::Stopped,
+            resumed: false,
+            failed: false,
+        });
+        Ok(Json::Null)
+    }
+
+    fn set_breakpoints(&mut self, arguments: &Json) -> Json {
+        let requested = arguments["breakpoints"]
+            .as_array()
+            .cloned()
+            .unwrap_or_default();
+        self.breakpoints = requested
+            .iter()
+            .filter_map(|b| {
+                Some(Breakpoint {
+                    x: b["column"].as_i64().map(|c| c - self.column_base),
+                    y: b["line"].as_i64()? - self.line_base,
+                })
+            })
+            .collect();
+        let breakpoints: Vec<Json> = requested
+            .iter()
+            .map(|b| json!({ "verified": true, "line": b["line"], "column": b["column"] }))
+            .collect();
+        json!({ "breakpoints": breakpoints })
+    }
+
+    /// stack_trace returns the frame the ><> is in, then one for each call in progress at the "C" which made it.
+    fn stack_trace(&self, arguments: &Json) -> Result<Json, String> {
+        let session = self.session()?;
+        let source = json!({ "name": session.path.rsplit('/').next(), "path": session.path });
+        let mut cells = session.calls.clone();
+        cells.push(session.codebox.position());
+        let frames: Vec<Json> = cells
+            .iter()
+            .enumerate()
+            .rev()
+            .map(|(i, &(x, y))| {
+                let name = match i {
+                    0 => String::from("main"),
+                    i => {
+                        let (cx, cy) = session.calls[i - 1];
+                        format!("call from ({}, {})", cx, cy)
+                    }
+                };
+                json!({
+                    "id": i,
+                    "name": name,
+                    "source": source,
+                    "line": y + self.line_base,
+                    "column": x + self.column_base,
+                })
+            })
+            .collect();
+        let start = arguments["startFrame"].as_u64().unwrap_or(0) as usize;
+        let levels = match arguments["levels"].as_u64() {
+            Some(0) | None => frames.len(),
+            Some(n) => n as usize,
+        };
+        Ok(json!({
+            "stackFrames": frames.iter().skip(start).take(levels).collect::<Vec<_>>(),
+            "totalFrames": frames.len(),
+        }))
+    }
+
+    /// variables returns the ><>'s state, its stacks, or the values and register of one stack.
+    fn variables(&self, arguments: &Json) -> Result<Json, String> {
+        let codebox = &self.session()?.codebox;
+        let variable = |name: String, value: String, reference: i64| json!({ "name": name, "value": value, "variablesReference": reference });
+        let variables = match arguments["variablesReference"].as_i64() {
+            Some(STATE) => {
+                let (x, y) = codebox.position();
+                vec![
+                    variable("position".into(), format!("({}, {})", x, y), 0),
+                    variable("direction".into(), format!("{:?}", codebox.direction()), 0),
+                    variable(
+                        "string mode".into(),
+                        codebox
+                            .string_mode()
+                            .map_or("off".into(), |q| format!("{}", q as char)),
+                        0,
+                    ),
+                    variable("deep sea".into(), codebox.deep_sea().to_string(), 0),
+                    variable(
+                        "current stack".into(),
+                        codebox.current_stack().to_string(),
+                        0,
+                    ),
+                ]
+            }
+            Some(STACKS) => codebox
+                .stacks()
+                .iter()
+                .enumerate()
+                .map(|(i, stack)| {
+                    let mut name = format!("stack {}", i);
+                    if i == codebox.current_stack() {
+                        name += " (current)";
+                    }
+                    variable(name, stack.to_string(), STACKS + 1 + i as i64)
+                })
+                .collect(),
+            Some(reference) => {
+                let stack = usize::try_from(reference - STACKS - 1)
+                    .ok()
+                    .and_then(|i| codebox.stacks().get(i))
+                    .ok_or("unknown variables reference")?;
+                let mut variables: Vec<Json> = stack
+                    .s
+                    .iter()
+                    .enumerate()
+                    .map(|(i, v)| variable(i.to_string(), v.to_string(), 0))
+                    .collect();
+                variables.push(variable(
+                    "register".into(),
+                    stack
+                        .register_value()
+                        .map_or("empty".into(), |v| v.to_string()),
+                    0,
+                ));
+                variables
+            }
+            None => return Err("expected a variables reference".into()),
+        };
+        Ok(json!({ "variables": variables }))
+    }
+
+    /// request handles a request from the client, returning the body of its response or why it failed.
+    fn request(&mut self, command: &str, arguments: &Json) -> Result<Json, String> {
+        match command {
+            "initialize" => {
+                let base = |key: &str| arguments[key].as_bool().unwrap_or(true) as i64;
+                self.line_base = base("linesStartAt1");
+                self.column_base = base("columnsStartAt1");
+                Ok(json!({
+                    "supportsConfigurationDoneRequest": true,
+                    "supportsTerminateRequest": true,
+                }))
+            }
+            "launch" => self.launch(arguments),
+            "setBreakpoints" => Ok(self.set_breakpoints(arguments)),
+            "configurationDone" => {
+                self.configured = true;
+                Ok(Json::Null)
+            }
+            "threads" => Ok(json!({ "threads": [{ "id": THREAD, "name": "><>" }] })),
+            "stackTrace" => self.stack_trace(arguments),
+            "scopes" => {
+                self.session()?;
+                Ok(json!({ "scopes": [
+                    { "name": "Stacks", "variablesReference": STACKS, "expensive": false },
+                    { "name": "><>", "variablesReference": STATE, "expensive": false },
+                ] }))
+            }
+            "variables" => self.variables(arguments),
+            "continue" | "next" | "stepIn" | "stepOut" => {
+                self.session()?;
+                Ok(json!({ "allThreadsContinued": true }))
+            }
+            "pause" => self.session().map(|_| Json::Null),
+            "disconnect" | "terminate" => {
+                self.done = true;
+                Ok(Json::Null)
+            }
+            _ => Err(format!("unknown request {}", command)),
+        }
+    }
+
+    /// after acts on a request once it's been answered, as DAP wants events like "initialized" and "stopped" to
+    /// follow the responses which cause them.
+    fn after(&mut self, command: &str) {
+        let depth = match &self.session {
+            Some(session) => session.calls.len(),
+            None if command == "initialize" => return self.event("initialized", json!({})),
+            None => return,
+        };
+        match command {
+            "launch" | "configurationDone" => self.start(),
+            "continue" => self.resume(Run::Continue),
+            "next" => self.resume(Run::Until(depth)),
+            "stepIn" => self.resume(Run::Until(usize::MAX)),
+            "stepOut" if depth == 0 => self.resume(Run::Continue),
+            "stepOut" => self.resume(Run::Until(depth - 1)),
+            "pause" if self.session.as_ref().unwrap().run != Run::Stopped => {
+                self.stopped("pause", None)
+            }
+            "terminate" => self.end(0),
+            _ => {}
+        }
+    }
+
+    fn handle(&mut self, message: &Json) {
+        if message["type"] != "request" {
+            return;
+        }
+        let command = message["command"].as_str().unwrap_or_default();
+        let mut response = json!({
+            "type": "response",
+            "request_seq": message["seq"],
+            "command": command,
+        });
+        match self.request(command, &message["arguments"]) {
+            Ok(body) => {
+                response["success"] = json!(true);
+                response["body"] = body;
+            }
+            Err(e) => {
+                response["success"] = json!(false);
+                response["message"] = json!(e);
+            }
+        }
+        self.send(response);
+        self.after(command);
+    }
+}
+
+/// dap serves the Debug Adapter Protocol over stdin and stdout until the client disconnects. Breakpoints are
+/// rows and columns of the codebox, and each call made by "C" is a stack frame.
+pub fn dap(options: CodeBoxOptions) {
+    let mut adapter = Adapter {
+        options,
+        out: io::stdout(),
+        seq: 0,
+        line_base: 1,
+        column_base: 1,
+        breakpoints: Vec::new(),
+        session: None,
+        stop_on_entry: false,
+        configured: false,
+        done: false,
+    };
+
+    // Messages are read on their own thread, so a pause can arrive while the ><> is swimming.
+    let (sender, messages) = mpsc::channel();
+    thread::spawn(move || {
+        let mut input = io::stdin().lock();
+        loop {
+            match read_message(&mut input) {
+                Ok(Some(message)) => {
+                    if sender.send(message).is_err() {
+                        break;
+                    }
+                }
+                Ok(None) => break,
+                Err(e) => {
+                    eprintln!("couldn't read from the client: {}", e);
+                    process::exit(1);
+                }
+            }
+        }
+    });
+
+    while !adapter.done {
+        let running = matches!(&adapter.session, Some(s) if s.run != Run::Stopped);
+        let message = if running {
+            match messages.try_recv() {
+                Ok(message) => Some(message),
+                Err(TryRecvError::Empty) => None,
+                Err(TryRecvError::Disconnected) => break,
+            }
+        } else {
+            match messages.recv() {
+                Ok(message) => Some(message),
+                Err(_) => break,
+            }
+        };
+        match message {
+            Some(message) => adapter.handle(&message),
+            None => adapter.swim(),
+        }
+    }
+    _ = adapter.out.flush();
+}
@@ -0,0 +1,393 @@
+use crate::CodeBoxOptions;
+use starfish::*;
+use std::fs;
+use std::io::{self, BufRead, Write};
+
+/// How many steps apart the checkpoints rewinding restores are taken.
+const CHECKPOINT_INTERVAL: u64 = 1000;
+
+const HELP: &str = "\
+commands:
+  step [N]            execute N instructions (default 1)
+  continue            run until a breakpoint, watch, halt or error
+  break X Y           stop before executing the cell at X, Y
+  break C             stop before executing instruction C
+  delete              remove every breakpoint and the watch
+  watch OP N          stop when the current stack's depth becomes OP N (OP is one of == != < <= > >=)
+  input TEXT          feed TEXT (followed by a newline) to the ><>'s input
+  rewind [N]          go back N steps (default 1)
+  save FILE           write the ><>'s complete state to FILE as JSON
+  load FILE           restore a state written by save
+  stacks              print every stack and its register
+  registers           print every register
+  info                print the ><>'s position, direction and modes
+  box                 print the codebox
+  help                print this message
+  quit                leave the debugger";
+
+/// Breakpoint stops execution before the ><> executes a matching cell.
+enum Breakpoint {
+    Cell(i64, i64),
+    Instruction(u8),
+}
+
+/// Watch stops execution when the depth of the current stack starts satisfying a comparison.
+struct Watch {
+    op: String,
+    depth: usize,
+}
+
+impl Watch {
+    fn holds(&self, depth: usize) -> bool {
+        match self.op.as_str() {
+            "==" => depth == self.depth,
+            "!=" => depth != self.depth,
+            "<" => depth < self.depth,
+            "<=" => depth <= self.depth,
+            ">" => depth > self.depth,
+            _ => depth >= self.depth,
+        }
+    }
+}
+
+/// Checkpoint is a snapshot rewinding can start replaying from.
+struct Checkpoint {
+    step: u64,
+    snapshot: Snapshot,
+    input: Vec<u8>,
+}
+
+struct Debugger {
+    script: String,
+    options: CodeBoxOptions,
+    codebox: CodeBox,
+    io: MemoryIo,
+    steps: u64,
+    // Input fed via "input", with the step it was fed at, so rewinding can replay it.
+    inputs: Vec<(u64, Vec<u8>)>,
+    // Snapshots taken every CHECKPOINT_INTERVAL steps, along with the input which hadn't been read yet.
+    checkpoints: Vec<Checkpoint>,
+    breakpoints: Vec<Breakpoint>,
+    watch: Option<Watch>,
+    // Why the ><> stopped swimming, if it has.
+    stopped: Option<String>,
+}
+
+impl Debugger {
+    fn new(script: String, options: CodeBoxOptions) -> Debugger {
+        let io = MemoryIo::default();
+        let codebox = options.builder(&script).io(io.clone()).build();
+        let checkpoint = Checkpoint {
+            step: 0,
+            snapshot: codebox.snapshot(),
+            input: Vec::new(),
+        };
+        Debugger {
+            script,
+            options,
+            codebox,
+            io,
+            steps: 0,
+            inputs: Vec::new(),
+            checkpoints: vec![checkpoint],
+            breakpoints: Vec::new(),
+            watch: None,
+            stopped: None,
+        }
+    }
+
+    /// step executes one instruction, returning false if the ><> can't swim any further.
+    fn step(&mut self) -> bool {
+        if let Some(reason) = &self.stopped {
+            println!("the ><> has stopped ({}); rewind to continue", reason);
+            return false;
+        }
+        let res = self.codebox.swim();
+        let output = self.io.take_output();
+        if !output.is_empty() {
+            _ = io::stdout().write_all(&output);
+            println!();
+        }
+        match res {
+            Ok(Step::Halt) => {
+                self.steps += 1;
+                self.stopped = Some(String::from("halted"));
+                println!("halted after {} steps", self.steps);
+                false
+            }
+            Ok(_) => {
+                self.steps += 1;
+                if self.steps.is_multiple_of(CHECKPOINT_INTERVAL) {
+                    self.checkpoint();
+                }
+                true
+            }
+            Err(e) => {
+                self.stopped = Some(e.to_string());
+                println!("something smells fishy... {}", e);
+                false
+            }
+        }
+    }
+
+    /// at_breakpoint reports whether the ><> is about to execute a cell matching a breakpoint.
+    fn at_breakpoint(&self) -> bool {
+        let (x, y) = self.codebox.position();
+        let instruction = self.codebox.cell(x, y).to_byte();
+        self.breakpoints.iter().any(|b| match b {
+            Breakpoint::Cell(bx, by) => (*bx, *by) == (x, y),
+            Breakpoint::Instruction(i) => Some(*i) == instruction,
+        })
+    }
+
+    fn depth(&self) -> usize {
+        self.codebox.stacks()[self.codebox.current_stack()].s.len()
+    }
+
+    /// run steps count times, or forever if count is None, stopping early at breakpoints and watches.
+    fn run(&mut self, count: Option<u64>) {
+        let mut done = 0;
+        while count.is_none_or(|c| done < c) {
+            let watched = self.watch.as_ref().map(|w| w.holds(self.depth()));
+            if !self.step() {
+                return;
+            }
+            done += 1;
+            if let (Some(watch), Some(false)) = (&self.watch, watched) {
+                if watch.holds(self.depth()) {
+                    println!("watch: depth is now {}", self.depth());
+                    break;
+                }
+            }
+            if self.at_breakpoint() {
+                println!("breakpoint");
+                break;
+            }
+        }
+        self.print_info();
+    }
+
+    /// checkpoint records the current state so rewinding can start from it.
+    fn checkpoint(&mut self) {
+        self.checkpoints.push(Checkpoint {
+            step: self.steps,
+            snapshot: self.codebox.snapshot(),
+            input: self.io.input(),
+        });
+    }
+
+    /// rewind goes back count steps, by restoring the last checkpoint before then and replaying from there.
+    fn rewind(&mut self, count: u64) {
+        let target = self.steps.saturating_sub(count);
+        self.inputs.retain(|(step, _)| *step <= target);
+        self.checkpoints.retain(|c| c.step <= target);
+        // The first checkpoint is never removed, as nothing happens before it.
+        let checkpoint = self.checkpoints.last().unwrap();
+        self.io = MemoryIo::new(&checkpoint.input);
+        self.codebox = self
+            .options
+            .builder(&self.script)
+            .io(self.io.clone())
+            .build();
+        self.codebox.restore(&checkpoint.snapshot);
+        self.steps = checkpoint.step;
+        self.stopped = None;
+
+        let start = self.steps;
+        let mut inputs = self
+            .inputs
+            .iter()
+            .skip_while(|(step, _)| *step < start)
+            .peekable();
+        loop {
+            while let Some((_, input)) = inputs.next_if(|(step, _)| *step == self.steps) {
+                self.io.push_input(input);
+            }
+            if self.steps == target {
+                break;
+            }
+            if self.codebox.swim().is_err() {
+                println!("the replay failed; input, \"x\" or the time may have differed");
+                break;
+            }
+            self.steps += 1;
+        }
+        self.io.take_output();
+        self.print_info();
+    }
+
+    /// save writes the ><>'s complete state to path.
+    fn save(&self, path: &str) {
+        match fs::write(path, self.codebox.snapshot().to_json()) {
+            Ok(()) => println!("saved step {} to {}", self.steps, path),
+            Err(e) => println!("couldn't write {}: {}", path, e),
+        }
+    }
+
+    /// load restores the state saved in path. Rewinding can't go back past it, so the step count starts again.
+    fn load(&mut self, path: &str) {
+        let snapshot = match fs::read_to_string(path)
+            .map_err(|e| e.into())
+            .and_then(|json| Snapshot::from_json(&json))
+        {
+            Ok(snapshot) => snapshot,
+            Err(e) => {
+                println!("couldn't load {}: {}", path, e);
+                return;
+            }
+        };
+        self.codebox.restore(&snapshot);
+        self.steps = 0;
+        self.stopped = None;
+        self.inputs.clear();
+        self.checkpoints.clear();
+        self.checkpoint();
+        self.print_info();
+    }
+
+    fn print_info(&self) {
+        let (x, y) = self.codebox.position();
+        let cell = self.codebox.cell(x, y);
+        let instruction = match cell.to_byte() {
+            Some(r) => format!("{:?}", r as char),
+            None => cell.to_string(),
+        };
+        print!(
+            "step {}: at ({}, {}) facing {:?} on {}",
+            self.steps,
+            x,
+            y,
+            self.codebox.direction(),
+            instruction
+        );
+        if let Some(quote) = self.codebox.string_mode() {
+            print!(", string mode ({})", quote as char);
+        }
+        if self.codebox.deep_sea() {
+            print!(", deep sea");
+        }
+        println!();
+    }
+
+    fn print_stacks(&self) {
+        for (i, stack) in self.codebox.stacks().iter().enumerate() {
+            let marker = if i == self.codebox.current_stack() {
+                '*'
+            } else {
+                ' '
+            };
+            print!("{}{}: {}", marker, i, stack);
+            if let Some(r) = stack.register_value() {
+                print!(" register: {}", r);
+            }
+            println!();
+        }
+    }
+
+    fn print_registers(&self) {
+        for (i, stack) in self.codebox.stacks().iter().enumerate() {
+            match stack.register_value() {
+                Some(r) => println!("{}: {}", i, r),
+                None => println!("{}: empty", i),
+            }
+        }
+    }
+
+    /// command runs a single line of debugger input, returning false when the debugger should exit.
+    fn command(&mut self, line: &str) -> bool {
+        let mut words = line.split_whitespace();
+        let cmd = match words.next() {
+            Some(cmd) => cmd,
+            None => return true,
+        };
+        let args: Vec<&str> = words.collect();
+
+        match cmd {
+            "s" | "step" => match args.first().map(|n| n.parse::<u64>()) {
+                None => self.run(Some(1)),
+                Some(Ok(n)) => self.run(Some(n)),
+                Some(Err(_)) => println!("step takes a number of steps"),
+            },
+            "c" | "continue" => self.run(None),
+            "b" | "break" => match args.as_slice() {
+                [x, y] => match (x.parse(), y.parse()) {
+                    (Ok(x), Ok(y)) => self.breakpoints.push(Breakpoint::Cell(x, y)),
+                    _ => println!("break takes X Y coordinates or a single instruction"),
+                },
+                [i] if i.len() == 1 => self
+                    .breakpoints
+                    .push(Breakpoint::Instruction(i.as_bytes()[0])),
+                _ => println!("break takes X Y coordinates or a single instruction"),
+            },
+            "d" | "delete" => {
+                self.breakpoints.clear();
+                self.watch = None;
+            }
+            "w" | "watch" => match args.as_slice() {
+                [op, n] if ["==", "!=", "<", "<=", ">", ">="].contains(op) => match n.parse() {
+                    Ok(depth) => {
+                        self.watch = Some(Watch {
+                            op: op.to_string(),
+                            depth,
+                        })
+                    }
+                    Err(_) => {
+                        println!("watch takes a comparison and a depth, e.g. \"watch >= 10\"")
+                    }
+                },
+                _ => println!("watch takes a comparison and a depth, e.g. \"watch >= 10\""),
+            },
+            "input" => {
+                let mut input = line.trim_start()[cmd.len()..]
+                    .trim_start()
+                    .as_bytes()
+                    .to_vec();
+                input.push(b'\n');
+                self.io.push_input(&input);
+                self.inputs.push((self.steps, input));
+            }
+            "r" | "rewind" => match args.first().map(|n| n.parse::<u64>()) {
+                None => self.rewind(1),
+                Some(Ok(n)) => self.rewind(n),
+                Some(Err(_)) => println!("rewind takes a number of steps"),
+            },
+            "save" => match args.as_slice() {
+                [path] => self.save(path),
+                _ => println!("save takes a file to write to"),
+            },
+            "load" => match args.as_slice() {
+                [path] => self.load(path),
+                _ => println!("load takes a file written by save"),
+            },
+            "stacks" => self.print_stacks(),
+            "registers" => self.print_registers(),
+            "i" | "info" => self.print_info(),
+            "box" => self.codebox.print(false),
+            "h" | "help" => println!("{}", HELP),
+            "q" | "quit" => return false,
+            _ => println!("unknown command {:?}, try \"help\"", cmd),
+        }
+        true
+    }
+}
+
+/// debug runs an interactive debugger over script, reading commands from stdin.
+pub fn debug(script: String, options: CodeBoxOptions) {
+    let mut debugger = Debugger::new(script, options);
+    debugger.print_info();
+
+    let stdin = io::stdin();
+    let mut lines = stdin.lock().lines();
+    loop {
+        print!("(fish) ");
+        _ = io::stdout().flush();
+        match lines.next() {
+            Some(Ok(line)) => {
+                if !debugger.command(&line) {
+                    break;
+                }
+            }
+            _ => break,
+        }
+    }
+}
@@ -0,0 +1,402 @@
+use crate::rpc::{read_message, write_message};
+use crate::CodeBoxOptions;
+use serde_json::{json, Value as Json};
+use starfish::*;
+use std::collections::{HashMap, HashSet};
+use std::io::{self, Write};
+use std::process;
+use std::time::Duration;
+
+/// TOKEN_TYPES are the semantic token types, indexed by the numbers in semantic token responses.
+const TOKEN_TYPES: [&str; 5] = ["string", "number", "keyword", "operator", "function"];
+
+/// RUN_LIMITS caps runs started from the "run" code lens, so one which never halts can't hang the server.
+const RUN_LIMITS: Limits = Limits {
+    max_steps: Some(10_000_000),
+    max_time: Some(Duration::from_secs(5)),
+    max_output: Some(1 << 20),
+};
+
+/// ErrorCode is a JSON-RPC error code.
+type ErrorCode = i64;
+const METHOD_NOT_FOUND: ErrorCode = -32601;
+const INVALID_PARAMS: ErrorCode = -32602;
+
+/// Server is a language server for one client, speaking over stdin and stdout.
+struct Server {
+    options: CodeBoxOptions,
+    documents: HashMap<String, String>,
+    shut_down: bool,
+}
+
+/// token_type returns the index in TOKEN_TYPES for r outside of a string, or None if it isn't an instruction.
+fn token_type(r: u8) -> Option<usize> {
+    match Instruction::from_byte(r)? {
+        Instruction::Nop => None,
+        i => Some(match i.info().category {
+            Category::Literal => 1,
+            Category::Movement | Category::Flow => 2,
+            Category::Arithmetic => 3,
+            Category::Stack | Category::Io | Category::Codebox | Category::Time => 4,
+        }),
+    }
+}
+
+/// string_cells returns every cell of codebox which is part of a string the ><> can reach, quotes included, as
+/// CodeBox.Explain finds them. Strings wrap around the edges of the codebox like the ><> does.
+fn string_cells(codebox: &CodeBox) -> HashSet<(i64, i64)> {
+    let (x0, y0) = codebox.origin();
+    let (width, height) = codebox.size();
+    let (width, height) = (width as i64, height as i64);
+    let mut cells = HashSet::new();
+    for annotation in codebox.explain().annotations {
+        if let Annotation::String {
+            mut x,
+            mut y,
+            dir,
+            length,
+            ..
+        } = annotation
+        {
+            for _ in 0..length {
+                cells.insert((x, y));
+                (x, y) = match dir {
+                    Direction::Right => (x0 + (x - x0 + 1).rem_euclid(width), y),
+                    Direction::Left => (x0 + (x - x0 - 1).rem_euclid(width), y),
+                    Direction::Down => (x, y0 + (y - y0 + 1).rem_euclid(height)),
+                    Direction::Up => (x, y0 + (y - y0 - 1).rem_euclid(height)),
+                };
+            }
+        }
+    }
+    cells
+}
+
+impl Server {
+    /// cells returns the cells of line, each with the UTF-16 column it starts at and how many columns it covers.
+    /// With Encoding::Byte, a character is the first of as many cells as it has bytes, and the others cover no
+    /// columns.
+    fn cells(&self, line: &str) -> Vec<(u32, u32, u32)> {
+        let mut cells = Vec::new();
+        let mut col = 0;
+        for c in line.chars() {
+            let width = c.len_utf16() as u32;
+            match self.options.encoding {
+                Encoding::Byte => {
+                    let mut bytes = [0; 4];
+                    for (i, &b) in c.encode_utf8(&mut bytes).as_bytes().iter().enumerate() {
+                        cells.push((b as u32, col, if i == 0 { width } else { 0 }));
+                    }
+                }
+                Encoding::Codepoint => cells.push((c as u32, col, width)),
+            }
+            col += width;
+        }
+        cells
+    }
+
+    /// column returns the UTF-16 column cell x of line starts at. Cells past the end of the line are taken to be
+    /// one column wide.
+    fn column(&self, line: &str, x: i64) -> u32 {
+        let cells = self.cells(line);
+        match cells.get(x.max(0) as usize) {
+            Some(&(_, col, _)) => col,
+            None => {
+                let end = line.encode_utf16().count() as u32;
+                end + (x as u32).saturating_sub(cells.len() as u32)
+            }
+        }
+    }
+
+    fn line<'a>(&'a self, uri: &str, y: usize) -> &'a str {
+        self.documents
+            .get(uri)
+            .and_then(|text| text.lines().nth(y))
+            .unwrap_or("")
+    }
+
+    /// codebox returns a CodeBox for script which can't touch the user's terminal or files.
+    fn codebox(&self, script: &str) -> CodeBox {
+        self.options
+            .builder(script)
+            .io(MemoryIo::default())
+            .file_system(MemoryFs::new())
+            .build()
+    }
+
+    /// diagnostics publishes the lint diagnostics for the document at uri.
+    fn diagnostics(&self, uri: &str) -> Json {
+        let Some(text) = self.documents.get(uri) else {
+            return json!({ "uri": uri, "diagnostics": [] });
+        };
+        let diagnostics: Vec<Json> = self
+            .codebox(text)
+            .lint()
+            .into_iter()
+            .filter(|d| d.y >= 0)
+            .map(|d| {
+                let line = self.line(uri, d.y as usize);
+                let start = self.column(line, d.x);
+                let end = self.column(line, d.x + d.length as i64).max(start + 1);
+                json!({
+                    "range": {
+                        "start": { "line": d.y, "character": start },
+                        "end": { "line": d.y, "character": end },
+                    },
+                    "severity": match d.severity {
+                        Severity::Error => 1,
+                        Severity::Warning => 2,
+                        Severity::Info => 3,
+                    },
+                    "code": d.kind,
+                    "source": "starfish",
+                    "message": d.message,
+                })
+            })
+            .collect();
+        json!({ "uri": uri, "diagnostics": diagnostics })
+    }
+
+    /// hover describes the cell under position.
+    fn hover(&self, params: &Json) -> Result<Json, (ErrorCode, String)> {
+        let (uri, line, character) = position(params)?;
+        let cells = self.cells(self.line(uri, line));
+        let Some(x) = cells
+            .iter()
+            .rposition(|&(_, col, width)| width > 0 && col <= character)
+        else {
+            return Ok(Json::Null);
+        };
+        let value = cells[x].0;
+        let text = self.documents.get(uri).map_or("", String::as_str);
+        let in_string = string_cells(&self.codebox(text)).contains(&(x as i64, line as i64));
+        let instruction = u8::try_from(value)
+            .ok()
+            .filter(|&r| r != 0)
+            .and_then(Instruction::from_byte);
+        let mut text = match instruction {
+            Some(instruction) => {
+                let info = instruction.info();
+                let mut text = format!("**`{}`** {}", info.byte as char, info.name);
+                if !info.effect.is_empty() {
+                    text += &format!(" `{}`", info.effect);
+                }
+                text += &format!("\n\n{}", info.description);
+                if !Dialect::Fish.has(instruction) {
+                    text += "\n\n*><> only: this isn't an instruction in ><>.";
+                }
+                text
+            }
+            None => format!("`{}` isn't an instruction.", value),
+        };
+        if in_string && value != b'"' as u32 && value != b'\'' as u32 {
+            text = format!("In a string, this pushes {}.\n\n---\n\n{}", value, text);
+        }
+        Ok(json!({
+            "contents": { "kind": "markdown", "value": text },
+            "range": {
+                "start": { "line": line, "character": cells[x].1 },
+                "end": { "line": line, "character": cells[x].1 + cells[x].2 },
+            },
+        }))
+    }
+
+    /// semantic_tokens classifies every cell of the document at uri, encoded as LSP's relative integers.
+    fn semantic_tokens(&self, uri: &str) -> Json {
+        let text = self.documents.get(uri).map_or("", String::as_str);
+        let mut data = Vec::new();
+        let mut last = (0, 0);
+        let strings = string_cells(&self.codebox(text));
+        for (y, line) in text.lines().enumerate() {
+            let cells = self.cells(line);
+            // Runs of cells of the same type are merged into one token.
+            let mut tokens: Vec<(u32, u32, usize)> = Vec::new();
+            for (i, &(value, col, width)) in cells.iter().enumerate() {
+                let kind = if strings.contains(&(i as i64, y as i64)) {
+                    Some(0)
+                } else {
+                    u8::try_from(value).ok().and_then(token_type)
+                };
+                match (kind, tokens.last_mut()) {
+                    (Some(kind), Some(token)) if token.2 == kind && token.0 + token.1 == col => {
+                        token.1 += width
+                    }
+                    (Some(kind), _) if width > 0 => tokens.push((col, width, kind)),
+                    _ => {}
+                }
+            }
+            for (col, width, kind) in tokens {
+                let (line, start) = (y as u32, col);
+                let delta_start = if line == last.0 {
+                    start - last.1
+                } else {
+                    start
+                };
+                data.extend([line - last.0, delta_start, width, kind as u32, 0]);
+                last = (line, start);
+            }
+        }
+        json!({ "data": data })
+    }
+
+    /// run runs the document at uri, returning what it output and why it stopped.
+    fn run(&self, uri: &str) -> Result<Json, (ErrorCode, String)> {
+        let text = self
+            .documents
+            .get(uri)
+            .ok_or((INVALID_PARAMS, format!("{} isn't open", uri)))?;
+        let report = self.codebox(text).run(RUN_LIMITS);
+        let exit = match &report.exit {
+            ExitReason::Halted => String::from("halted"),
+            ExitReason::Error(e) => e.to_string(),
+            ExitReason::StepLimit => format!("stopped after {} steps", report.steps),
+            ExitReason::TimeLimit => String::from("stopped after running for too long"),
+            ExitReason::OutputLimit => String::from("stopped after outputting too much"),
+        };
+        Ok(json!({
+            "output": String::from_utf8_lossy(&report.output),
+            "exit": exit,
+            "steps": report.steps,
+        }))
+    }
+
+    /// request handles a request from the client, returning its result or an error.
+    fn request(&mut self, method: &str, params: &Json) -> Result<Json, (ErrorCode, String)> {
+        match method {
+            "initialize" => Ok(json!({
+                "capabilities": {
+                    "textDocumentSync": 1,
+                    "hoverProvider": true,
+                    "codeLensProvider": { "resolveProvider": false },
+                    "executeCommandProvider": { "commands": ["starfish.run"] },
+                    "semanticTokensProvider": {
+                        "legend": { "tokenTypes": TOKEN_TYPES, "tokenModifiers": [] },
+                        "full": true,
+                    },
+                },
+                "serverInfo": { "name": "starfish", "version": env!("CARGO_PKG_VERSION") },
+            })),
+            "shutdown" => {
+                self.shut_down = true;
+                Ok(Json::Null)
+            }
+            "textDocument/hover" => self.hover(params),
+            "textDocument/codeLens" => {
+                let uri = document(params)?;
+                Ok(json!([{
+                    "range": {
+                        "start": { "line": 0, "character": 0 },
+                        "end": { "line": 0, "character": 0 },
+                    },
+                    "command": { "title": "▶ Run", "command": "starfish.run", "arguments": [uri] },
+                }]))
+            }
+            "textDocument/semanticTokens/full" => Ok(self.semantic_tokens(document(params)?)),
+            "workspace/executeCommand" => match params["command"].as_str() {
+                Some("starfish.run") => {
+                    let uri = params["arguments"][0]
+                        .as_str()
+                        .ok_or((INVALID_PARAMS, String::from("expected a document to run")))?;
+                    self.run(uri)
+                }
+                command => Err((INVALID_PARAMS, format!("unknown command {:?}", command))),
+            },
+            _ => Err((METHOD_NOT_FOUND, format!("unknown method {}", method))),
+        }
+    }
+
+    /// notification handles a notification from the client, returning any notification to send back.
+    fn notification(&mut self, method: &str, params: &Json) -> Option<Json> {
+        let uri = params["textDocument"]["uri"].as_str()?.to_string();
+        match method {
+            "textDocument/didOpen" => {
+                let text = params["textDocument"]["text"].as_str()?;
+                self.documents.insert(uri.clone(), text.to_string());
+            }
+            "textDocument/didChange" => {
+                // Only full syncs are asked for, so the last change holds the whole document.
+                let text = params["contentChanges"].as_array()?.last()?["text"].as_str()?;
+                self.documents.insert(uri.clone(), text.to_string());
+            }
+            "textDocument/didClose" => _ = self.documents.remove(&uri),
+            _ => return None,
+        }
+        Some(json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/publishDiagnostics",
+            "params": self.diagnostics(&uri),
+        }))
+    }
+}
+
+/// document returns the URI of the document params are about.
+fn document(params: &Json) -> Result<&str, (ErrorCode, String)> {
+    params["textDocument"]["uri"]
+        .as_str()
+        .ok_or((INVALID_PARAMS, String::from("expected a textDocument")))
+}
+
+/// position returns the URI, line and UTF-16 column params point at.
+fn position(params: &Json) -> Result<(&str, usize, u32), (ErrorCode, String)> {
+    let uri = document(params)?;
+    let position = &params["position"];
+    match (position["line"].as_u64(), position["character"].as_u64()) {
+        (Some(line), Some(character)) => Ok((uri, line as usize, character as u32)),
+        _ => Err((INVALID_PARAMS, String::from("expected a position"))),
+    }
+}
+
+/// lsp serves the Language Server Protocol over stdin and stdout until the client exits.
+pub fn lsp(options: CodeBoxOptions) {
+    let mut server = Server {
+        options,
+        documents: HashMap::new(),
+        shut_down: false,
+    };
+    let mut input = io::stdin().lock();
+    let mut out = io::stdout().lock();
+    fn send(out: &mut impl Write, message: &Json) {
+        if let Err(e) = write_message(out, message) {
+            eprintln!("couldn't write to the client: {}", e);
+            process::exit(1);
+        }
+    }
+
+    loop {
+        let message = match read_message(&mut input) {
+            Ok(Some(message)) => message,
+            Ok(None) => break,
+            Err(e) => {
+                eprintln!("couldn't read from the client: {}", e);
+                process::exit(1);
+            }
+        };
+        let method = message["method"].as_str().unwrap_or_default();
+        let params = &message["params"];
+        if method == "exit" {
+            break;
+        }
+        match message.get("id") {
+            Some(id) if !method.is_empty() => {
+                let response = match server.request(method, params) {
+                    Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+                    Err((code, message)) => json!({
+                        "jsonrpc": "2.0",
+                        "id": id,
+                        "error": { "code": code, "message": message },
+                    }),
+                };
+                send(&mut out, &response);
+            }
+            // Responses to requests the server never sends.
+            Some(_) => {}
+            None => {
+                if let Some(notification) = server.notification(method, params) {
+                    send(&mut out, &notification);
+                }
+            }
+        }
+    }
+    _ = out.flush();
+    process::exit(if server.shut_down { 0 } else { 1 });
+}
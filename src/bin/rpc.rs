@@ -0,0 +1,56 @@
+use serde_json::Value as Json;
+use std::io::{self, BufRead, Write};
+
+/// MAX_MESSAGE is the longest message body read_message accepts, so a bad Content-Length can't make it allocate
+/// more than the client could ever send.
+const MAX_MESSAGE: usize = 64 << 20;
+
+/// read_message reads one JSON message framed by a Content-Length header, as LSP and DAP clients send them. It
+/// returns None once the input ends.
+pub fn read_message(input: &mut impl BufRead) -> io::Result<Option<Json>> {
+    let mut length = None;
+    let length = loop {
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            if let Some(length) = length {
+                break length;
+            }
+            continue;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.eq_ignore_ascii_case("Content-Length") {
+                length = Some(value.trim().parse::<usize>().map_err(|e| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("bad Content-Length: {}", e),
+                    )
+                })?);
+            }
+        }
+    };
+    if length > MAX_MESSAGE {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "Content-Length {} is over the limit of {} bytes",
+                length, MAX_MESSAGE
+            ),
+        ));
+    }
+    let mut body = vec![0; length];
+    input.read_exact(&mut body)?;
+    serde_json::from_slice(&body)
+        .map(Some)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+/// write_message writes message to out, framed by a Content-Length header.
+pub fn write_message(out: &mut impl Write, message: &Json) -> io::Result<()> {
+    let body = message.to_string();
+    write!(out, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    out.flush()
+}
@@ -0,0 +1,173 @@
+use serde::Serialize;
+use serde_json::json;
+use starfish::*;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::ops::Range;
+use std::str::FromStr;
+
+/// StepRange is the steps given to --trace-steps, counting from 0. Either end may be left out.
+#[derive(Debug, Clone)]
+pub struct StepRange(Range<u64>);
+
+impl FromStr for StepRange {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<StepRange, String> {
+        let invalid = || format!("invalid step range {:?} (example: 100..200)", s);
+        let (start, end) = s.split_once("..").ok_or_else(invalid)?;
+        let parse = |n: &str, default| match n {
+            "" => Ok(default),
+            n => n.parse().map_err(|_| invalid()),
+        };
+        Ok(StepRange(parse(start, 0)?..parse(end, u64::MAX)?))
+    }
+}
+
+/// TraceFilter chooses which steps are written to a trace.
+#[derive(Debug, Clone, Default)]
+pub struct TraceFilter {
+    /// instructions are the only instructions to trace, if given.
+    pub instructions: Option<Vec<char>>,
+    pub steps: Option<StepRange>,
+    /// stack_changes only traces steps which change the current stack, or which stack is current.
+    pub stack_changes: bool,
+}
+
+/// Record is one step of a trace.
+#[derive(Serialize)]
+struct Record<'a> {
+    step: u64,
+    x: i64,
+    y: i64,
+    direction: Direction,
+    /// instruction is the value of the cell executed, which is a byte unless the cell was set to something else.
+    instruction: serde_json::Value,
+    string_mode: Option<char>,
+    deep_sea: bool,
+    /// stack is the index of the current stack before the step, and stack_after the index after it.
+    stack: usize,
+    stack_after: usize,
+    before: Vec<serde_json::Value>,
+    after: Vec<serde_json::Value>,
+    register: Option<serde_json::Value>,
+    output: Option<&'a str>,
+    error: Option<String>,
+}
+
+/// Before is the state of the ><> before a step, kept until the step is finished.
+pub struct Before {
+    x: i64,
+    y: i64,
+    direction: Direction,
+    instruction: Value,
+    string_mode: Option<u8>,
+    deep_sea: bool,
+    stack: usize,
+    values: Vec<Value>,
+}
+
+/// TraceLog writes one JSON record per step to a file.
+pub struct TraceLog {
+    out: BufWriter<File>,
+    filter: TraceFilter,
+    step: u64,
+}
+
+/// json returns v as a JSON number if it can be one exactly, or as a string otherwise. Whole numbers are written
+/// without a fractional part whatever the NumericMode.
+fn json(v: &Value) -> serde_json::Value {
+    match v {
+        Value::Float(f) if f.fract() == 0.0 && f.abs() < 9007199254740992.0 => json!(*f as i64),
+        Value::Float(f) if f.is_finite() => json!(f),
+        Value::Int(i) => json!(i),
+        v => match v.to_string().parse::<i64>() {
+            Ok(i) => json!(i),
+            Err(_) => json!(v.to_string()),
+        },
+    }
+}
+
+impl TraceLog {
+    /// create starts a trace at path, replacing anything already there.
+    pub fn create(path: &str, filter: TraceFilter) -> io::Result<TraceLog> {
+        Ok(TraceLog {
+            out: BufWriter::new(File::create(path)?),
+            filter,
+            step: 0,
+        })
+    }
+
+    /// before returns the state of codebox which the next step's record needs, or None if the step is filtered out
+    /// before it's run.
+    pub fn before(&self, codebox: &CodeBox) -> Option<Before> {
+        if let Some(steps) = &self.filter.steps {
+            if !steps.0.contains(&self.step) {
+                return None;
+            }
+        }
+        let (x, y) = codebox.position();
+        if let Some(instructions) = &self.filter.instructions {
+            if !instructions.contains(&codebox.cell_char(x, y)) {
+                return None;
+            }
+        }
+        let stack = codebox.current_stack();
+        Some(Before {
+            x,
+            y,
+            direction: codebox.direction(),
+            instruction: codebox.cell(x, y),
+            string_mode: codebox.string_mode(),
+            deep_sea: codebox.deep_sea(),
+            stack,
+            values: codebox.stacks()[stack].s.clone(),
+        })
+    }
+
+    /// after writes the record for a step which started as before, if the filter lets it through.
+    pub fn after(
+        &mut self,
+        before: Option<Before>,
+        codebox: &CodeBox,
+        result: &Result<Step, StarfishError>,
+    ) -> io::Result<()> {
+        let step = self.step;
+        self.step += 1;
+        let Some(before) = before else {
+            return Ok(());
+        };
+        let stack_after = codebox.current_stack();
+        let after = &codebox.stacks()[stack_after];
+        if self.filter.stack_changes && before.stack == stack_after && before.values == after.s {
+            return Ok(());
+        }
+
+        let record = Record {
+            step,
+            x: before.x,
+            y: before.y,
+            direction: before.direction,
+            instruction: json(&before.instruction),
+            string_mode: before.string_mode.map(char::from),
+            deep_sea: before.deep_sea,
+            stack: before.stack,
+            stack_after,
+            before: before.values.iter().map(json).collect(),
+            after: after.s.iter().map(json).collect(),
+            register: after.register_value().map(json),
+            output: match result {
+                Ok(Step::Output(s)) => Some(s),
+                _ => None,
+            },
+            error: result.as_ref().err().map(|e| e.to_string()),
+        };
+        serde_json::to_writer(&mut self.out, &record)?;
+        writeln!(self.out)
+    }
+
+    /// finish flushes the trace to its file.
+    pub fn finish(mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
@@ -0,0 +1,294 @@
+use crate::CodeBoxOptions;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, Paragraph, Wrap};
+use ratatui::{DefaultTerminal, Frame};
+use starfish::*;
+use std::collections::VecDeque;
+use std::io;
+use std::time::{Duration, Instant};
+
+/// How many previous positions of the ><> are highlighted.
+const TRAIL_LEN: usize = 8;
+/// How long to spend swimming between redraws when there's no delay.
+const FRAME: Duration = Duration::from_millis(16);
+/// The longest the visualizer waits for one "S", however long the ><> asks to sleep for.
+const MAX_SLEEP: Duration = Duration::from_secs(60 * 60);
+
+struct Visualizer {
+    codebox: CodeBox,
+    io: MemoryIo,
+    output: String,
+    trail: VecDeque<(i64, i64)>,
+    steps: u64,
+    delay: u64,
+    paused: bool,
+    // Whether the viewport scrolls to keep the ><> in view.
+    follow: bool,
+    // The codebox coordinates of the top left cell in view.
+    scroll: (i64, i64),
+    // Whether keypresses go to the ><>'s input instead of controlling the visualizer.
+    input_mode: bool,
+    // Why the ><> stopped swimming, if it has.
+    stopped: Option<String>,
+}
+
+impl Visualizer {
+    /// step executes one instruction, returning how long the ><> asked to sleep for.
+    fn step(&mut self) -> Duration {
+        if self.stopped.is_some() {
+            return Duration::ZERO;
+        }
+        self.trail.push_front(self.codebox.position());
+        self.trail.truncate(TRAIL_LEN);
+
+        let res = self.codebox.swim();
+        self.output
+            .push_str(&String::from_utf8_lossy(&self.io.take_output()));
+        match res {
+            Ok(step) => {
+                self.steps += 1;
+                match step {
+                    Step::Halt => self.stopped = Some(String::from("halted")),
+                    Step::Sleep(ms) => {
+                        return Duration::try_from_secs_f64(ms.max(0.0) / 1000.0)
+                            .map_or(MAX_SLEEP, |sleep| sleep.min(MAX_SLEEP))
+                    }
+                    Step::Continue | Step::Output(_) => {}
+                }
+            }
+            Err(e) => self.stopped = Some(format!("something smells fishy... {}", e)),
+        }
+        Duration::ZERO
+    }
+
+    /// key handles a keypress, returning false when the visualizer should exit.
+    fn key(&mut self, code: KeyCode) -> bool {
+        if self.input_mode {
+            match code {
+                KeyCode::Tab | KeyCode::Esc => self.input_mode = false,
+                KeyCode::Enter => self.io.push_input(b"\n"),
+                KeyCode::Char(c) => self.io.push_input(c.to_string().as_bytes()),
+                _ => {}
+            }
+            return true;
+        }
+
+        match code {
+            KeyCode::Char('q') | KeyCode::Esc => return false,
+            KeyCode::Char(' ') => self.paused = !self.paused,
+            KeyCode::Char('n') => {
+                self.paused = true;
+                self.step();
+            }
+            KeyCode::Char('+') | KeyCode::Char('=') => self.delay /= 2,
+            KeyCode::Char('-') => self.delay = (self.delay * 2).clamp(10, 5000),
+            KeyCode::Char('f') => self.follow = true,
+            KeyCode::Tab => self.input_mode = true,
+            KeyCode::Left => self.scroll_by(-1, 0),
+            KeyCode::Right => self.scroll_by(1, 0),
+            KeyCode::Up => self.scroll_by(0, -1),
+            KeyCode::Down => self.scroll_by(0, 1),
+            _ => {}
+        }
+        true
+    }
+
+    fn scroll_by(&mut self, dx: i64, dy: i64) {
+        self.follow = false;
+        let (x0, y0) = self.codebox.origin();
+        let (width, height) = self.codebox.size();
+        self.scroll.0 = (self.scroll.0 + dx).clamp(x0, x0 + width as i64 - 1);
+        self.scroll.1 = (self.scroll.1 + dy).clamp(y0, y0 + height as i64 - 1);
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let [main, output, status] = Layout::vertical([
+            Constraint::Min(3),
+            Constraint::Length(8),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+        let [code, stacks] =
+            Layout::horizontal([Constraint::Min(10), Constraint::Length(32)]).areas(main);
+
+        self.draw_code_box(frame, code);
+        self.draw_stacks(frame, stacks);
+
+        let block = Block::bordered().title(" output ");
+        let height = block.inner(output).height as usize;
+        let lines: Vec<&str> = self.output.split('\n').collect();
+        let text = lines[lines.len().saturating_sub(height)..].join("\n");
+        frame.render_widget(Paragraph::new(text).block(block), output);
+
+        let state = match &self.stopped {
+            Some(reason) => reason.clone(),
+            None if self.input_mode => String::from("typing input (tab to stop)"),
+            None if self.paused => String::from("paused"),
+            None => String::from("running"),
+        };
+        let help = " | space pause  n step  +/- speed  arrows scroll  f follow  tab input  q quit";
+        frame.render_widget(
+            Paragraph::new(format!(
+                "step {} | delay {}ms | {}{}",
+                self.steps, self.delay, state, help
+            ))
+            .style(Style::new().add_modifier(Modifier::REVERSED)),
+            status,
+        );
+    }
+
+    fn draw_code_box(&mut self, frame: &mut Frame, area: Rect) {
+        let (f_x, f_y) = self.codebox.position();
+        let arrow = match self.codebox.direction() {
+            Direction::Right => '→',
+            Direction::Down => '↓',
+            Direction::Left => '←',
+            Direction::Up => '↑',
+        };
+        let mut title = format!(" codebox ({}, {}) {} ", f_x, f_y, arrow);
+        if let Some(quote) = self.codebox.string_mode() {
+            title.push_str(&format!("[string {}] ", quote as char));
+        }
+        if self.codebox.deep_sea() {
+            title.push_str("[deep sea] ");
+        }
+        let block = Block::bordered().title(title);
+        let inner = block.inner(area);
+        let (view_w, view_h) = (inner.width as i64, inner.height as i64);
+
+        if self.follow {
+            if f_x < self.scroll.0 {
+                self.scroll.0 = f_x;
+            } else if f_x >= self.scroll.0 + view_w {
+                self.scroll.0 = f_x + 1 - view_w;
+            }
+            if f_y < self.scroll.1 {
+                self.scroll.1 = f_y;
+            } else if f_y >= self.scroll.1 + view_h {
+                self.scroll.1 = f_y + 1 - view_h;
+            }
+        }
+
+        // Only the cells in view are looked at, since "p" can make the codebox's bounds huge.
+        let (x0, y0) = self.codebox.origin();
+        let (width, height) = self.codebox.size();
+        let (x1, y1) = (x0 + width as i64, y0 + height as i64);
+        let lines: Vec<Line> = (self.scroll.1.max(y0)..(self.scroll.1 + view_h).min(y1))
+            .map(|y| {
+                Line::from(
+                    (self.scroll.0.max(x0)..(self.scroll.0 + view_w).min(x1))
+                        .map(|x| {
+                            let c = self.codebox.cell_char(x, y);
+                            let c = if c.is_control() { ' ' } else { c };
+                            let style = if (x, y) == (f_x, f_y) {
+                                Style::new()
+                                    .fg(Color::Black)
+                                    .bg(Color::Yellow)
+                                    .add_modifier(Modifier::BOLD)
+                            } else if let Some(age) = self.trail.iter().position(|&p| p == (x, y)) {
+                                let shade = 120 - (age * 80 / TRAIL_LEN) as u8;
+                                Style::new().bg(Color::Rgb(shade / 2, shade / 2, shade))
+                            } else {
+                                Style::new()
+                            };
+                            Span::styled(c.to_string(), style)
+                        })
+                        .collect::<Vec<_>>(),
+                )
+            })
+            .collect();
+        frame.render_widget(Paragraph::new(lines).block(block), area);
+    }
+
+    fn draw_stacks(&self, frame: &mut Frame, area: Rect) {
+        let lines: Vec<Line> = self
+            .codebox
+            .stacks()
+            .iter()
+            .enumerate()
+            .rev()
+            .map(|(i, stack)| {
+                let mut text = format!("{}: {}", i, stack);
+                if let Some(r) = stack.register_value() {
+                    text.push_str(&format!(" &{}", r));
+                }
+                if i == self.codebox.current_stack() {
+                    Line::styled(text, Style::new().add_modifier(Modifier::BOLD))
+                } else {
+                    Line::raw(text)
+                }
+            })
+            .collect();
+        frame.render_widget(
+            Paragraph::new(lines)
+                .wrap(Wrap { trim: false })
+                .block(Block::bordered().title(" stacks ")),
+            area,
+        );
+    }
+
+    fn run(&mut self, terminal: &mut DefaultTerminal) -> io::Result<()> {
+        let mut next_tick = Instant::now();
+        loop {
+            terminal.draw(|frame| self.draw(frame))?;
+
+            let running = !self.paused && self.stopped.is_none();
+            let timeout = if running {
+                next_tick.saturating_duration_since(Instant::now())
+            } else {
+                Duration::from_millis(250)
+            };
+            if event::poll(timeout)? {
+                if let Event::Key(key) = event::read()? {
+                    if key.kind == KeyEventKind::Press && !self.key(key.code) {
+                        return Ok(());
+                    }
+                }
+                continue;
+            }
+            if !running || Instant::now() < next_tick {
+                continue;
+            }
+
+            if self.delay == 0 {
+                let frame_end = Instant::now() + FRAME;
+                let mut sleep = Duration::ZERO;
+                while self.stopped.is_none() && sleep.is_zero() && Instant::now() < frame_end {
+                    sleep = self.step();
+                }
+                next_tick = Instant::now() + sleep;
+            } else {
+                let sleep = self.step();
+                next_tick = Instant::now() + sleep + Duration::from_millis(self.delay);
+            }
+        }
+    }
+}
+
+/// visualize runs script in a full-screen terminal UI, with delay milliseconds between each tick.
+pub fn visualize(script: &str, options: &CodeBoxOptions, delay: u64) -> io::Result<()> {
+    let io = MemoryIo::default();
+    let codebox = options.builder(script).io(io.clone()).build();
+    let mut visualizer = Visualizer {
+        codebox,
+        io,
+        output: String::new(),
+        trail: VecDeque::with_capacity(TRAIL_LEN + 1),
+        steps: 0,
+        delay,
+        paused: false,
+        follow: true,
+        scroll: (0, 0),
+        input_mode: false,
+        stopped: None,
+    };
+
+    // init also makes sure the terminal is restored if we panic.
+    let mut terminal = ratatui::init();
+    let res = visualizer.run(&mut terminal);
+    ratatui::restore();
+    res
+}
@@ -0,0 +1,327 @@
+use std::ffi::{c_char, c_int, CStr, CString};
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+use std::slice;
+use std::time::Duration;
+
+use crate::{CodeBox, DenyFs, ExitReason, Limits, MemoryIo, Stack, Step};
+
+/// The call succeeded. After stepping or running, the ><> hasn't halted yet.
+pub const STARFISH_OK: c_int = 0;
+/// The ><> has halted. Stepping or running it again does nothing.
+pub const STARFISH_HALTED: c_int = 1;
+/// A required pointer was NULL.
+pub const STARFISH_ERR_NULL: c_int = -1;
+/// An argument was invalid, like a script which isn't UTF-8 or an initial stack which can't be parsed.
+pub const STARFISH_ERR_ARGUMENT: c_int = -2;
+/// Something smelled fishy: the ><> failed, and stays on the failing cell.
+pub const STARFISH_ERR_SCRIPT: c_int = -3;
+/// The interpreter panicked. The VM shouldn't be used again, other than to free it.
+pub const STARFISH_ERR_PANIC: c_int = -4;
+
+/// StarfishVm is the opaque starfish_vm handle. Its input and output are kept in memory, "S" doesn't really
+/// sleep, and "F" can't use any files.
+pub struct StarfishVm {
+    codebox: CodeBox,
+    io: MemoryIo,
+    // Output taken from io which hasn't been read yet.
+    output: Vec<u8>,
+    halted: bool,
+    // The message for the last error, returned by starfish_vm_error.
+    error: Option<CString>,
+}
+
+impl StarfishVm {
+    /// fail records message as the VM's last error, returning code.
+    fn fail(&mut self, code: c_int, message: impl ToString) -> c_int {
+        // A message with a NUL in it is cut short there.
+        let message = message.to_string();
+        let message = message.split('\0').next().unwrap_or_default();
+        self.error = Some(CString::new(message).unwrap_or_default());
+        code
+    }
+
+    /// take_output moves everything written since the last call into output.
+    fn take_output(&mut self) {
+        self.output.extend(self.io.take_output());
+    }
+}
+
+/// guard runs f, turning a panic into STARFISH_ERR_PANIC rather than unwinding into C.
+fn guard(f: impl FnOnce() -> c_int) -> c_int {
+    panic::catch_unwind(AssertUnwindSafe(f)).unwrap_or(STARFISH_ERR_PANIC)
+}
+
+/// starfish_vm_new creates a VM for the NUL-terminated script source, starting with the values in stack, written
+/// as the command line's --stack is. stack may be NULL for an empty stack. On success *vm is set to the new VM,
+/// which must be freed with starfish_vm_free.
+///
+/// # Safety
+///
+/// source and stack, unless NULL, must be NUL-terminated strings, and vm must point to writable memory.
+#[no_mangle]
+pub unsafe extern "C" fn starfish_vm_new(
+    source: *const c_char,
+    stack: *const c_char,
+    vm: *mut *mut StarfishVm,
+) -> c_int {
+    if source.is_null() || vm.is_null() {
+        return STARFISH_ERR_NULL;
+    }
+    guard(|| {
+        let Ok(source) = CStr::from_ptr(source).to_str() else {
+            return STARFISH_ERR_ARGUMENT;
+        };
+        let stack = if stack.is_null() {
+            Stack::new(None)
+        } else {
+            match CStr::from_ptr(stack).to_str().map(Stack::from_string) {
+                Ok(Ok(stack)) => stack,
+                _ => return STARFISH_ERR_ARGUMENT,
+            }
+        };
+        let io = MemoryIo::default();
+        let codebox = CodeBox::builder(source)
+            .stack(stack)
+            .io(io.clone())
+            .file_system(DenyFs)
+            .build();
+        *vm = Box::into_raw(Box::new(StarfishVm {
+            codebox,
+            io,
+            output: Vec::new(),
+            halted: false,
+            error: None,
+        }));
+        STARFISH_OK
+    })
+}
+
+/// starfish_vm_free frees vm. It does nothing if vm is NULL.
+///
+/// # Safety
+///
+/// vm must be NULL or a VM from starfish_vm_new which hasn't already been freed.
+#[no_mangle]
+pub unsafe extern "C" fn starfish_vm_free(vm: *mut StarfishVm) {
+    if !vm.is_null() {
+        drop(Box::from_raw(vm));
+    }
+}
+
+/// starfish_vm_step executes up to steps instructions, returning STARFISH_HALTED if the ><> halts,
+/// STARFISH_ERR_SCRIPT if it fails, or STARFISH_OK.
+///
+/// # Safety
+///
+/// vm must be NULL or a VM from starfish_vm_new.
+#[no_mangle]
+pub unsafe extern "C" fn starfish_vm_step(vm: *mut StarfishVm, steps: u64) -> c_int {
+    let Some(vm) = vm.as_mut() else {
+        return STARFISH_ERR_NULL;
+    };
+    guard(|| {
+        if vm.halted {
+            return STARFISH_HALTED;
+        }
+        for _ in 0..steps {
+            match vm.codebox.swim() {
+                Ok(Step::Halt) => {
+                    vm.halted = true;
+                    break;
+                }
+                Ok(_) => {}
+                Err(e) => {
+                    vm.take_output();
+                    return vm.fail(STARFISH_ERR_SCRIPT, e);
+                }
+            }
+        }
+        vm.take_output();
+        if vm.halted {
+            STARFISH_HALTED
+        } else {
+            STARFISH_OK
+        }
+    })
+}
+
+/// starfish_vm_run runs until the ><> halts or fails, or it has executed max_steps instructions or run for
+/// max_ms milliseconds, where 0 means no limit. It returns what starfish_vm_step does.
+///
+/// # Safety
+///
+/// vm must be NULL or a VM from starfish_vm_new.
+#[no_mangle]
+pub unsafe extern "C" fn starfish_vm_run(
+    vm: *mut StarfishVm,
+    max_steps: u64,
+    max_ms: u64,
+) -> c_int {
+    let Some(vm) = vm.as_mut() else {
+        return STARFISH_ERR_NULL;
+    };
+    guard(|| {
+        if vm.halted {
+            return STARFISH_HALTED;
+        }
+        let limits = Limits {
+            max_steps: Some(max_steps).filter(|&n| n > 0),
+            max_time: Some(Duration::from_millis(max_ms)).filter(|d| !d.is_zero()),
+            max_output: None,
+        };
+        let report = vm.codebox.run(limits);
+        vm.take_output();
+        match report.exit {
+            ExitReason::Halted => {
+                vm.halted = true;
+                STARFISH_HALTED
+            }
+            ExitReason::Error(e) => vm.fail(STARFISH_ERR_SCRIPT, e),
+            _ => STARFISH_OK,
+        }
+    })
+}
+
+/// starfish_vm_feed_input queues len bytes of input for "i" to read.
+///
+/// # Safety
+///
+/// vm must be NULL or a VM from starfish_vm_new, and input must point to len readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn starfish_vm_feed_input(
+    vm: *mut StarfishVm,
+    input: *const u8,
+    len: usize,
+) -> c_int {
+    let Some(vm) = vm.as_mut() else {
+        return STARFISH_ERR_NULL;
+    };
+    if input.is_null() && len > 0 {
+        return STARFISH_ERR_NULL;
+    }
+    if len > 0 {
+        vm.io.push_input(slice::from_raw_parts(input, len));
+    }
+    STARFISH_OK
+}
+
+/// starfish_vm_output_len returns how many bytes of output are waiting to be read, or 0 if vm is NULL.
+///
+/// # Safety
+///
+/// vm must be NULL or a VM from starfish_vm_new.
+#[no_mangle]
+pub unsafe extern "C" fn starfish_vm_output_len(vm: *const StarfishVm) -> usize {
+    vm.as_ref().map_or(0, |vm| vm.output.len())
+}
+
+/// starfish_vm_read_output moves up to cap bytes of the ><>'s output into buf, returning how many were moved.
+/// Anything which doesn't fit is kept for the next call.
+///
+/// # Safety
+///
+/// vm must be NULL or a VM from starfish_vm_new, and buf must point to cap writable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn starfish_vm_read_output(
+    vm: *mut StarfishVm,
+    buf: *mut u8,
+    cap: usize,
+) -> usize {
+    let Some(vm) = vm.as_mut() else {
+        return 0;
+    };
+    if buf.is_null() {
+        return 0;
+    }
+    let n = cap.min(vm.output.len());
+    ptr::copy_nonoverlapping(vm.output.as_ptr(), buf, n);
+    vm.output.drain(..n);
+    n
+}
+
+/// starfish_vm_stack_count returns how many stacks there are, or 0 if vm is NULL.
+///
+/// # Safety
+///
+/// vm must be NULL or a VM from starfish_vm_new.
+#[no_mangle]
+pub unsafe extern "C" fn starfish_vm_stack_count(vm: *const StarfishVm) -> usize {
+    vm.as_ref().map_or(0, |vm| vm.codebox.stacks().len())
+}
+
+/// starfish_vm_current_stack returns the index of the stack instructions operate on, counting from the bottom.
+///
+/// # Safety
+///
+/// vm must be NULL or a VM from starfish_vm_new.
+#[no_mangle]
+pub unsafe extern "C" fn starfish_vm_current_stack(vm: *const StarfishVm) -> usize {
+    vm.as_ref().map_or(0, |vm| vm.codebox.current_stack())
+}
+
+/// starfish_vm_stack copies up to cap values of the stack at index into values, bottom first, and sets *len to
+/// how many values the stack holds. values may be NULL when cap is 0, to find out how big the stack is.
+///
+/// # Safety
+///
+/// vm must be NULL or a VM from starfish_vm_new, values must point to cap writable doubles, and len must point to
+/// a writable size_t.
+#[no_mangle]
+pub unsafe extern "C" fn starfish_vm_stack(
+    vm: *const StarfishVm,
+    index: usize,
+    values: *mut f64,
+    cap: usize,
+    len: *mut usize,
+) -> c_int {
+    let Some(vm) = vm.as_ref() else {
+        return STARFISH_ERR_NULL;
+    };
+    if len.is_null() || (values.is_null() && cap > 0) {
+        return STARFISH_ERR_NULL;
+    }
+    let Some(stack) = vm.codebox.stacks().get(index) else {
+        return STARFISH_ERR_ARGUMENT;
+    };
+    for (i, v) in stack.s.iter().take(cap).enumerate() {
+        *values.add(i) = v.to_f64();
+    }
+    *len = stack.s.len();
+    STARFISH_OK
+}
+
+/// starfish_vm_position sets *x and *y to the coordinates of the ><>.
+///
+/// # Safety
+///
+/// vm must be NULL or a VM from starfish_vm_new, and x and y must point to writable int64_ts.
+#[no_mangle]
+pub unsafe extern "C" fn starfish_vm_position(
+    vm: *const StarfishVm,
+    x: *mut i64,
+    y: *mut i64,
+) -> c_int {
+    let Some(vm) = vm.as_ref() else {
+        return STARFISH_ERR_NULL;
+    };
+    if x.is_null() || y.is_null() {
+        return STARFISH_ERR_NULL;
+    }
+    (*x, *y) = vm.codebox.position();
+    STARFISH_OK
+}
+
+/// starfish_vm_error returns the message for the last STARFISH_ERR_SCRIPT, or NULL if there hasn't been one. It's
+/// valid until the next call which fails, or until vm is freed.
+///
+/// # Safety
+///
+/// vm must be NULL or a VM from starfish_vm_new.
+#[no_mangle]
+pub unsafe extern "C" fn starfish_vm_error(vm: *const StarfishVm) -> *const c_char {
+    match vm.as_ref().and_then(|vm| vm.error.as_ref()) {
+        Some(error) => error.as_ptr(),
+        None => ptr::null(),
+    }
+}
@@ -0,0 +1,78 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+
+use crate::{Instruction, Stack};
+
+/// Action is what a custom instruction asks the CodeBox to do once it's run.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Action {
+    /// Carry on swimming.
+    Continue,
+    /// Output the string, as "o" and "n" do.
+    Output(String),
+    /// Stop, as ";" does.
+    Halt,
+    /// Move the ><> to x/y, as "." does.
+    Jump(i64, i64),
+}
+
+/// CustomInstruction is a host function run when the ><> swims onto its instruction. It's given the current stack,
+/// and any values it pushes should be in the CodeBox's NumericMode. An Err fails the instruction with the message.
+pub type CustomInstruction = Box<dyn FnMut(&mut Stack) -> Result<Action, String>>;
+
+/// Registry holds the custom instructions a CodeBox understands on top of the built-in ones. It's given to a
+/// CodeBox with CodeBoxBuilder.Registry.
+#[derive(Default)]
+pub struct Registry {
+    instructions: HashMap<u8, CustomInstruction>,
+}
+
+impl Registry {
+    /// new returns an empty registry.
+    pub fn new() -> Registry {
+        Registry::default()
+    }
+
+    /// register makes r run f. It's an error for r to be a built-in instruction in any dialect; use
+    /// Registry.Shadow to replace one.
+    pub fn register(
+        &mut self,
+        r: u8,
+        f: impl FnMut(&mut Stack) -> Result<Action, String> + 'static,
+    ) -> Result<(), Box<dyn Error>> {
+        if Instruction::from_byte(r).is_some() {
+            return Err(format!("{:?} is a built-in instruction", r as char))?;
+        }
+        self.shadow(r, f);
+        Ok(())
+    }
+
+    /// shadow makes r run f, even if it replaces a built-in instruction.
+    pub fn shadow(&mut self, r: u8, f: impl FnMut(&mut Stack) -> Result<Action, String> + 'static) {
+        self.instructions.insert(r, Box::new(f));
+    }
+
+    /// contains returns whether r is a custom instruction.
+    pub fn contains(&self, r: u8) -> bool {
+        !self.instructions.is_empty() && self.instructions.contains_key(&r)
+    }
+
+    /// get returns the custom instruction r, if there is one.
+    pub(crate) fn get(&mut self, r: u8) -> Option<&mut CustomInstruction> {
+        if self.instructions.is_empty() {
+            return None;
+        }
+        self.instructions.get_mut(&r)
+    }
+}
+
+impl fmt::Debug for Registry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut keys: Vec<char> = self.instructions.keys().map(|&r| r as char).collect();
+        keys.sort_unstable();
+        f.debug_struct("Registry")
+            .field("instructions", &keys)
+            .finish()
+    }
+}
@@ -0,0 +1,111 @@
+use std::{error, fmt, io};
+
+use crate::{Direction, Value};
+
+/// Location records where the ><> was, and which way it was swimming, when an error occurred.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Location {
+    pub x: i64,
+    pub y: i64,
+    pub direction: Direction,
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "({}, {}) facing {:?}", self.x, self.y, self.direction)
+    }
+}
+
+/// StarfishError is returned by CodeBox.Swim whenever the ><> does something the language doesn't allow. In *><>
+/// terms, every variant is "something smells fishy...".
+#[derive(Debug)]
+pub enum StarfishError {
+    /// An instruction needed more values than the current stack holds.
+    StackUnderflow(Location),
+    /// There's no stack to move to or return to: "]", "D" or "R" on the bottom stack, or "I" on the top one.
+    EmptyRegisterStack(Location),
+    /// The ><> swam onto a byte that isn't an instruction.
+    InvalidInstruction(u8, Location),
+    /// The ><> swam onto a cell holding a value which isn't a byte, so can't be an instruction.
+    InvalidCell(Value, Location),
+    /// ".", "C" or "R" tried to move the ><> outside of the codebox.
+    OutOfBoundsJump(Location),
+    /// "g" or "p" referenced a cell outside of the codebox.
+    OutOfBoundsAccess(Location),
+    /// "," or "%" was asked to divide by zero.
+    DivisionByZero(Location),
+    /// An arithmetic instruction overflowed NumericMode::Integer.
+    Overflow(Location),
+    /// "o" was asked to output a value that isn't a character.
+    InvalidCharacter(f64, Location),
+    /// "i" read bytes which aren't UTF-8 while using Encoding::Codepoint.
+    InvalidInput(Vec<u8>, Location),
+    /// A custom instruction returned an error.
+    InstructionFailed(u8, String, Location),
+    /// "F" failed to read or write a file.
+    FileError(io::Error, Location),
+    /// Writing "o" or "n" output, or flushing it for "S", failed.
+    OutputError(io::Error, Location),
+}
+
+impl StarfishError {
+    /// location returns where the ><> was when the error occurred.
+    pub fn location(&self) -> Location {
+        match self {
+            StarfishError::StackUnderflow(loc)
+            | StarfishError::EmptyRegisterStack(loc)
+            | StarfishError::InvalidInstruction(_, loc)
+            | StarfishError::InvalidCell(_, loc)
+            | StarfishError::OutOfBoundsJump(loc)
+            | StarfishError::OutOfBoundsAccess(loc)
+            | StarfishError::DivisionByZero(loc)
+            | StarfishError::Overflow(loc)
+            | StarfishError::InvalidCharacter(_, loc)
+            | StarfishError::InvalidInput(_, loc)
+            | StarfishError::InstructionFailed(_, _, loc)
+            | StarfishError::FileError(_, loc)
+            | StarfishError::OutputError(_, loc) => *loc,
+        }
+    }
+}
+
+impl fmt::Display for StarfishError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StarfishError::StackUnderflow(loc) => write!(f, "stack underflow at {}", loc),
+            StarfishError::EmptyRegisterStack(loc) => write!(f, "no stack to switch to at {}", loc),
+            StarfishError::InvalidInstruction(b, loc) => {
+                write!(f, "invalid instruction {:?} at {}", *b as char, loc)
+            }
+            StarfishError::InvalidCell(v, loc) => {
+                write!(f, "cell holding {} isn't an instruction at {}", v, loc)
+            }
+            StarfishError::OutOfBoundsJump(loc) => write!(f, "jump out of bounds at {}", loc),
+            StarfishError::OutOfBoundsAccess(loc) => {
+                write!(f, "codebox access out of bounds at {}", loc)
+            }
+            StarfishError::DivisionByZero(loc) => write!(f, "division by zero at {}", loc),
+            StarfishError::Overflow(loc) => write!(f, "integer overflow at {}", loc),
+            StarfishError::InvalidCharacter(v, loc) => {
+                write!(f, "invalid character {} at {}", v, loc)
+            }
+            StarfishError::InvalidInput(bytes, loc) => {
+                write!(f, "invalid UTF-8 input {:x?} at {}", bytes, loc)
+            }
+            StarfishError::InstructionFailed(r, e, loc) => {
+                write!(f, "instruction {:?} failed at {}: {}", *r as char, loc, e)
+            }
+            StarfishError::FileError(e, loc) => write!(f, "file error at {}: {}", loc, e),
+            StarfishError::OutputError(e, loc) => write!(f, "output error at {}: {}", loc, e),
+        }
+    }
+}
+
+impl error::Error for StarfishError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            StarfishError::FileError(e, _) | StarfishError::OutputError(e, _) => Some(e),
+            _ => None,
+        }
+    }
+}
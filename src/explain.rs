@@ -0,0 +1,350 @@
+use std::fmt;
+
+use crate::{CodeBox, Direction, Instruction, StarfishError, Step, Value};
+
+/// Annotation explains a cell the ><> can reach, found by CodeBox.Explain.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Annotation {
+    /// A cell executed as a built-in instruction.
+    Instruction {
+        x: i64,
+        y: i64,
+        instruction: Instruction,
+    },
+    /// A string literal, opened by the quote at x/y and read swimming in dir. length counts the cells from the
+    /// opening quote to the closing one, and text is what's between them.
+    String {
+        x: i64,
+        y: i64,
+        dir: Direction,
+        length: u64,
+        text: String,
+    },
+    /// A cell executed as a custom instruction from the CodeBox's Registry.
+    Custom { x: i64, y: i64, r: u8 },
+    /// A cell which isn't an instruction, so the ><> fails if it gets there.
+    Invalid { x: i64, y: i64, value: Value },
+}
+
+impl Annotation {
+    /// position returns the x/y coordinates of the annotated cell.
+    pub fn position(&self) -> (i64, i64) {
+        match *self {
+            Annotation::Instruction { x, y, .. }
+            | Annotation::String { x, y, .. }
+            | Annotation::Custom { x, y, .. }
+            | Annotation::Invalid { x, y, .. } => (x, y),
+        }
+    }
+}
+
+impl fmt::Display for Annotation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Annotation::Instruction { instruction, .. } => {
+                let info = instruction.info();
+                write!(f, "{:?} {}", info.byte as char, info.name)?;
+                if !info.effect.is_empty() {
+                    write!(f, " ({})", info.effect)?;
+                }
+                write!(f, ": {}", info.description)
+            }
+            Annotation::String {
+                dir, length, text, ..
+            } => {
+                let pushes = length.saturating_sub(2);
+                write!(
+                    f,
+                    "string {:?}, read swimming {}, pushes {} value{}",
+                    text,
+                    direction_name(*dir),
+                    pushes,
+                    if pushes == 1 { "" } else { "s" }
+                )
+            }
+            Annotation::Custom { r, .. } => write!(f, "{:?} custom instruction", *r as char),
+            Annotation::Invalid { value, .. } => {
+                write!(f, "holds {}, which isn't an instruction", value)
+            }
+        }
+    }
+}
+
+/// Explanation is what CodeBox.Explain finds.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Explanation {
+    /// annotations are sorted by position.
+    pub annotations: Vec<Annotation>,
+    /// complete is false when the ><> jumps to coordinates worked out while running, so cells only reached that
+    /// way aren't annotated.
+    pub complete: bool,
+}
+
+/// direction_name returns how narrations and annotations write dir.
+fn direction_name(dir: Direction) -> &'static str {
+    match dir {
+        Direction::Right => "right",
+        Direction::Down => "down",
+        Direction::Left => "left",
+        Direction::Up => "up",
+    }
+}
+
+/// list joins values as English does, like "1, 2 and 3".
+fn list(values: &[Value]) -> String {
+    match values {
+        [] => String::new(),
+        [v] => v.to_string(),
+        [init @ .., last] => {
+            let init: Vec<String> = init.iter().map(|v| v.to_string()).collect();
+            format!("{} and {}", init.join(", "), last)
+        }
+    }
+}
+
+/// Before is what CodeBox.Narrate needs to remember from before the ><> swims.
+struct Before {
+    x: i64,
+    y: i64,
+    dir: Direction,
+    r: u8,
+    value: Option<Value>,
+    string_mode: u8,
+    deep_sea: bool,
+    // The top three values of the current stack, bottom first, and how many values it held.
+    top: Vec<Value>,
+    len: usize,
+    register: Option<Value>,
+    // The top two values of the stack below the current one, where "C" keeps the return address.
+    below: Vec<Value>,
+    file_open: bool,
+}
+
+impl Before {
+    /// popped returns the top n values of the stack, bottom first.
+    fn popped(&self, n: usize) -> &[Value] {
+        &self.top[self.top.len().saturating_sub(n)..]
+    }
+
+    /// coords returns the top two values of stack as x/y coordinates, as "." and "C" pop them.
+    fn coords(stack: &[Value]) -> (i64, i64) {
+        match stack {
+            [.., x, y] => (x.to_f64() as i64, y.to_f64() as i64),
+            _ => (0, 0),
+        }
+    }
+}
+
+impl CodeBox {
+    /// explain annotates every cell the ><> can reach from where it is, found as CodeBox.Lint finds them: each
+    /// instruction, each string literal with the text it pushes, and each cell which isn't an instruction. Empty
+    /// cells and spaces aren't annotated, and a string's opening quote is only annotated as the string.
+    pub fn explain(&self) -> Explanation {
+        let walk = self.walk();
+        let mut annotations = Vec::new();
+        for &(x, y, dir) in &walk.strings {
+            let r = self.grid.get(x, y);
+            let mut text = String::new();
+            let mut length = 2;
+            let (mut cx, mut cy) = self.grid.next(x, y, dir);
+            while (cx, cy) != (x, y)
+                && (self.grid.get(cx, cy) != r || self.grid.value(cx, cy).is_some())
+            {
+                text.push(self.cell_char(cx, cy));
+                length += 1;
+                (cx, cy) = self.grid.next(cx, cy, dir);
+            }
+            annotations.push(Annotation::String {
+                x,
+                y,
+                dir,
+                length,
+                text,
+            });
+        }
+        for &(x, y) in &walk.executed {
+            let r = self.grid.get(x, y);
+            annotations.push(match (self.grid.value(x, y), Instruction::from_byte(r)) {
+                (None, _) if self.registry.contains(r) => Annotation::Custom { x, y, r },
+                (None, Some(Instruction::Nop)) => continue,
+                (None, Some(Instruction::DoubleQuote | Instruction::SingleQuote))
+                    if walk.strings.iter().any(|&(sx, sy, _)| (sx, sy) == (x, y)) =>
+                {
+                    continue
+                }
+                (None, Some(instruction)) => Annotation::Instruction { x, y, instruction },
+                _ => Annotation::Invalid {
+                    x,
+                    y,
+                    value: self.cell(x, y),
+                },
+            });
+        }
+        annotations.sort_by_key(|a| {
+            let (x, y) = a.position();
+            let dir = match a {
+                Annotation::String { dir, .. } => Some(*dir as u8),
+                _ => None,
+            };
+            (y, x, dir)
+        });
+        Explanation {
+            annotations,
+            complete: !walk.computed,
+        }
+    }
+
+    /// narrate swims like CodeBox.Swim, also returning a sentence saying what the ><> did, like "pushed 10" or
+    /// "called subroutine at (5, 2)".
+    pub fn narrate(&mut self) -> Result<(Step, String), StarfishError> {
+        let stack = &self.stacks[self.p].s;
+        let before = Before {
+            x: self.f_x,
+            y: self.f_y,
+            dir: self.f_dir,
+            r: self.grid.get(self.f_x, self.f_y),
+            value: self.grid.value(self.f_x, self.f_y).cloned(),
+            string_mode: self.string_mode,
+            deep_sea: self.deep_sea,
+            top: stack[stack.len().saturating_sub(3)..].to_vec(),
+            len: stack.len(),
+            register: self.stacks[self.p].register_value().cloned(),
+            below: match self.p.checked_sub(1) {
+                Some(p) => {
+                    let stack = &self.stacks[p].s;
+                    stack[stack.len().saturating_sub(2)..].to_vec()
+                }
+                None => Vec::new(),
+            },
+            file_open: self.file.is_some(),
+        };
+        let step = self.swim()?;
+        let sentence = self.describe(&before, &step);
+        Ok((step, sentence))
+    }
+
+    /// describe returns the sentence CodeBox.Narrate returns for the step the ><> just took.
+    fn describe(&self, b: &Before, step: &Step) -> String {
+        let top = |n: usize| {
+            let stack = &self.stacks[self.p].s;
+            list(&stack[stack.len().saturating_sub(n)..])
+        };
+        if b.value.is_some() || (b.string_mode != 0 && b.r != b.string_mode) {
+            let c = self.cell_char(b.x, b.y);
+            return format!("pushed {} ({:?}) as part of a string", top(1), c);
+        }
+        if self.registry.contains(b.r) {
+            return format!("ran the custom instruction {:?}", b.r as char);
+        }
+        let instruction = match Instruction::from_byte(b.r) {
+            Some(instruction) if !b.deep_sea || instruction.info().deep_sea => instruction,
+            _ => return format!("ignored {:?} in deep-sea mode", b.r as char),
+        };
+        let dir = direction_name(self.f_dir);
+        let popped = |n| list(b.popped(n));
+        let (x, y) = match b.popped(2) {
+            [x, y] => (x.to_string(), y.to_string()),
+            _ => Default::default(),
+        };
+
+        use Instruction::*;
+        match instruction {
+            Nop => String::from("did nothing"),
+            Random => format!("picked a random direction, {}", dir),
+            _ if instruction.info().turns && self.f_dir == b.dir => {
+                format!("kept swimming {}", dir)
+            }
+            _ if instruction.info().turns => format!("turned to swim {}", dir),
+            Skip => String::from("skipped the next cell"),
+            SkipIfZero if b.popped(1).first().is_some_and(Value::is_zero) => {
+                String::from("popped 0, so skipped the next cell")
+            }
+            SkipIfZero => format!("popped {}, so didn't skip the next cell", popped(1)),
+            Jump => {
+                let (x, y) = Before::coords(&b.top);
+                format!("jumped to ({}, {})", x, y)
+            }
+            Halt => String::from("halted"),
+            DoubleQuote | SingleQuote if self.string_mode != 0 => String::from("started a string"),
+            DoubleQuote | SingleQuote => String::from("ended the string"),
+            Push0 | Push1 | Push2 | Push3 | Push4 | Push5 | Push6 | Push7 | Push8 | Push9
+            | PushA | PushB | PushC | PushD | PushE | PushF => format!("pushed {}", top(1)),
+            Add => format!("added {} and {}, pushing {}", x, y, top(1)),
+            Subtract => format!("subtracted {} from {}, pushing {}", y, x, top(1)),
+            Multiply => format!("multiplied {} by {}, pushing {}", x, y, top(1)),
+            Divide => format!("divided {} by {}, pushing {}", x, y, top(1)),
+            Modulo => format!("took {} modulo {}, pushing {}", x, y, top(1)),
+            Equal | Greater | Less => {
+                let relation = match instruction {
+                    Equal => "equal to",
+                    Greater => "greater than",
+                    _ => "less than",
+                };
+                let holds = !self.stacks[self.p].s.last().is_some_and(Value::is_zero);
+                format!(
+                    "pushed {}, as {} {} {} {}",
+                    top(1),
+                    x,
+                    if holds { "is" } else { "isn't" },
+                    relation,
+                    y
+                )
+            }
+            Duplicate => format!("duplicated {}", popped(1)),
+            Drop => format!("dropped {}", popped(1)),
+            Swap => format!("swapped {} and {}", x, y),
+            Rotate => format!("moved {} back two places", popped(1)),
+            ShiftRight => String::from("shifted the stack right"),
+            ShiftLeft => String::from("shifted the stack left"),
+            Reverse => String::from("reversed the stack"),
+            Length => format!("pushed the stack's length, {}", top(1)),
+            NewStack => format!("moved {} values onto a new stack", popped(1)),
+            CloseStack => format!(
+                "closed the stack, moving its {} values onto the one below",
+                b.len
+            ),
+            Register => match &b.register {
+                Some(v) => format!("pushed {} from the register", v),
+                None => format!("put {} in the register", popped(1)),
+            },
+            OutputChar | OutputNumber => match step {
+                Step::Output(s) => format!("output {:?}", s),
+                _ => String::from("output nothing"),
+            },
+            Input
+                if self.stacks[self.p]
+                    .s
+                    .last()
+                    .is_some_and(|v| v.to_f64() < 0.0) =>
+            {
+                String::from("pushed -1, as there's no input left")
+            }
+            Input => format!("read {} from input", top(1)),
+            Get => format!("pushed {} from the cell at ({}, {})", top(1), x, y),
+            Put => match b.popped(3) {
+                [v, _, _] => format!("put {} in the cell at ({}, {})", v, x, y),
+                _ => format!("put a value in the cell at ({}, {})", x, y),
+            },
+            Hour => format!("pushed the hour, {}", top(1)),
+            Minute => format!("pushed the minute, {}", top(1)),
+            Second => format!("pushed the second, {}", top(1)),
+            Sleep => format!("slept for {} tenths of a second", popped(1)),
+            DeepSea => String::from("dove into deep-sea mode"),
+            Surface => String::from("surfaced from deep-sea mode"),
+            File if b.file_open => format!("wrote to {:?} and closed it", self.file_path),
+            File => format!("opened {:?} to read from", self.file_path),
+            Call => {
+                let (x, y) = Before::coords(&b.top);
+                format!("called subroutine at ({}, {})", x, y)
+            }
+            Return => {
+                let (x, y) = Before::coords(&b.below);
+                format!("returned to just after the call at ({}, {})", x, y)
+            }
+            StackUp => format!("moved up to stack {}", self.p),
+            StackDown => format!("moved down to stack {}", self.p),
+            Right | Down | Left | Up | Mirror | BackMirror | VerticalMirror | HorizontalMirror
+            | TurnAround | Hook => unreachable!("movement is described above"),
+        }
+    }
+}
@@ -0,0 +1,217 @@
+use std::collections::BTreeMap;
+#[cfg(not(target_arch = "wasm32"))]
+use std::fs::{self, File};
+use std::io::{self, Cursor, Read};
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+/// FileSystem is where "F" opens and writes files. "F" opens a file for "i" to read from, creating it if it doesn't
+/// exist, and the next "F" replaces the file's contents and closes it.
+pub trait FileSystem {
+    /// open returns a reader for the file at path, creating an empty file there if there isn't one.
+    fn open(&mut self, path: &str) -> io::Result<Box<dyn Read>>;
+
+    /// write replaces the contents of the file at path with data, creating it if it doesn't exist.
+    fn write(&mut self, path: &str, data: &[u8]) -> io::Result<()>;
+}
+
+/// normalize resolves "." and ".." in path without touching the file system. The result is relative: a path
+/// starting at the root is taken to start at whatever directory the FileSystem is confined to. It's an error for
+/// ".." to leave that directory, or for path to name no file at all.
+fn normalize(path: &str) -> io::Result<PathBuf> {
+    let mut normal = PathBuf::new();
+    for component in Path::new(path).components() {
+        match component {
+            Component::Normal(c) => normal.push(c),
+            Component::ParentDir => {
+                if !normal.pop() {
+                    return Err(denied(path));
+                }
+            }
+            Component::CurDir | Component::RootDir | Component::Prefix(_) => {}
+        }
+    }
+    if normal.as_os_str().is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{:?} doesn't name a file", path),
+        ));
+    }
+    Ok(normal)
+}
+
+/// denied returns the error for a path a FileSystem won't let the ><> use.
+fn denied(path: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::PermissionDenied,
+        format!("access to {:?} is denied", path),
+    )
+}
+
+/// RealFs uses the host's file system, with paths relative to the working directory. It's the default.
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RealFs;
+
+/// open_file opens the file at path on the host for reading, creating it if it doesn't exist.
+#[cfg(not(target_arch = "wasm32"))]
+fn open_file(path: &Path) -> io::Result<Box<dyn Read>> {
+    let file = File::open(path).or_else(|_| {
+        File::create(path)?;
+        File::open(path)
+    })?;
+    Ok(Box::new(file))
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl FileSystem for RealFs {
+    fn open(&mut self, path: &str) -> io::Result<Box<dyn Read>> {
+        open_file(Path::new(path))
+    }
+
+    fn write(&mut self, path: &str, data: &[u8]) -> io::Result<()> {
+        fs::write(path, data)
+    }
+}
+
+/// RootFs uses the host's file system, confined to a directory. Paths are relative to the directory, including
+/// ones starting with "/", and can't use ".." or symlinks to get out of it.
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Debug, Clone)]
+pub struct RootFs {
+    root: PathBuf,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl RootFs {
+    /// new returns a RootFs confined to root, which must be an existing directory.
+    pub fn new(root: impl AsRef<Path>) -> io::Result<RootFs> {
+        let root = root.as_ref().canonicalize()?;
+        if !root.is_dir() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{} isn't a directory", root.display()),
+            ));
+        }
+        Ok(RootFs { root })
+    }
+
+    /// resolve returns where path is on the host, if it's inside the root once any symlinks are followed.
+    fn resolve(&self, path: &str) -> io::Result<PathBuf> {
+        let full = self.root.join(normalize(path)?);
+        let real = match full.canonicalize() {
+            Ok(real) => real,
+            // The file doesn't exist yet, so check where it would be created. Something which can't be followed
+            // but is still there, like a symlink to a missing file, would be followed when creating the file, and
+            // could lead anywhere.
+            Err(_) => match (full.parent(), full.file_name()) {
+                (Some(parent), Some(name)) => {
+                    let real = parent.canonicalize()?.join(name);
+                    if fs::symlink_metadata(&real).is_ok() {
+                        return Err(denied(path));
+                    }
+                    real
+                }
+                _ => return Err(denied(path)),
+            },
+        };
+        if !real.starts_with(&self.root) {
+            return Err(denied(path));
+        }
+        Ok(real)
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl FileSystem for RootFs {
+    fn open(&mut self, path: &str) -> io::Result<Box<dyn Read>> {
+        open_file(&self.resolve(path)?)
+    }
+
+    fn write(&mut self, path: &str, data: &[u8]) -> io::Result<()> {
+        fs::write(self.resolve(path)?, data)
+    }
+}
+
+/// MemoryFs keeps files in memory. Paths are normalized like RootFs's, so "a", "./a" and "/a" are the same file.
+/// Clones share the same files, so keep one around to seed files and inspect them after handing another to a
+/// CodeBox.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryFs {
+    files: Arc<Mutex<BTreeMap<PathBuf, Vec<u8>>>>,
+}
+
+impl MemoryFs {
+    /// new returns an empty MemoryFs.
+    pub fn new() -> MemoryFs {
+        MemoryFs::default()
+    }
+
+    /// from_dir returns a MemoryFs holding a copy of every file under dir on the host, with paths relative to dir.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn from_dir(dir: impl AsRef<Path>) -> io::Result<MemoryFs> {
+        fn walk(memory: &MemoryFs, dir: &Path, prefix: &Path) -> io::Result<()> {
+            for entry in fs::read_dir(dir)? {
+                let entry = entry?;
+                let path = prefix.join(entry.file_name());
+                if entry.file_type()?.is_dir() {
+                    walk(memory, &entry.path(), &path)?;
+                } else {
+                    let contents = fs::read(entry.path())?;
+                    memory.files.lock().unwrap().insert(path, contents);
+                }
+            }
+            Ok(())
+        }
+        let memory = MemoryFs::new();
+        walk(&memory, dir.as_ref(), Path::new(""))?;
+        Ok(memory)
+    }
+
+    /// insert creates or replaces the file at path.
+    pub fn insert(&self, path: &str, contents: &[u8]) -> io::Result<()> {
+        let path = normalize(path)?;
+        self.files.lock().unwrap().insert(path, contents.to_vec());
+        Ok(())
+    }
+
+    /// get returns a copy of the contents of the file at path, or None if there isn't one.
+    pub fn get(&self, path: &str) -> Option<Vec<u8>> {
+        let path = normalize(path).ok()?;
+        self.files.lock().unwrap().get(&path).cloned()
+    }
+
+    /// paths returns the path of every file, in order.
+    pub fn paths(&self) -> Vec<PathBuf> {
+        self.files.lock().unwrap().keys().cloned().collect()
+    }
+}
+
+impl FileSystem for MemoryFs {
+    fn open(&mut self, path: &str) -> io::Result<Box<dyn Read>> {
+        let path = normalize(path)?;
+        let mut files = self.files.lock().unwrap();
+        let contents = files.entry(path).or_default().clone();
+        Ok(Box::new(Cursor::new(contents)))
+    }
+
+    fn write(&mut self, path: &str, data: &[u8]) -> io::Result<()> {
+        let path = normalize(path)?;
+        self.files.lock().unwrap().insert(path, data.to_vec());
+        Ok(())
+    }
+}
+
+/// DenyFs refuses every file, so "F" always fails. It's the default on wasm, which has no file system.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DenyFs;
+
+impl FileSystem for DenyFs {
+    fn open(&mut self, path: &str) -> io::Result<Box<dyn Read>> {
+        Err(denied(path))
+    }
+
+    fn write(&mut self, path: &str, _data: &[u8]) -> io::Result<()> {
+        Err(denied(path))
+    }
+}
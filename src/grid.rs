@@ -0,0 +1,214 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::{Direction, Encoding, NumericMode, Value};
+
+/// Grid is the codebox: an unbounded plane of cells, each of which is 0 until something is put there, apart from the
+/// rectangle holding the script, where lines shorter than the longest are padded with spaces. The script is kept
+/// densely, since that's where nearly all of the swimming happens, and cells "p" writes anywhere else are kept
+/// sparsely.
+///
+/// Cells holding a byte, which every instruction is, are stored as one. A cell can hold any value though, and those
+/// holding anything else are kept in a map of their own, with 0 in their place among the bytes. Such a cell is never
+/// an instruction.
+///
+/// The ><> wraps around at the edges of the grid's bounds, the smallest rectangle containing the script and every
+/// cell which has been written to. The bounds only ever grow.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) struct Grid {
+    // The size of the dense area, which starts at (0, 0).
+    width: i64,
+    height: i64,
+    dense: Vec<u8>,
+    #[serde(with = "cells")]
+    sparse: HashMap<(i64, i64), u8>,
+    #[serde(with = "cells")]
+    values: HashMap<(i64, i64), Value>,
+    // The corners of the bounds, inclusive.
+    min: (i64, i64),
+    max: (i64, i64),
+}
+
+impl Grid {
+    /// new returns a grid holding script, with its first line at y = 0. Each cell holds a byte of the script, or a
+    /// character's code point with Encoding::Codepoint, as a value in mode. Cells past the end of a line hold spaces.
+    pub fn new(script: &str, encoding: Encoding, mode: NumericMode) -> Grid {
+        let lines: Vec<Vec<u32>> = script
+            .lines()
+            .map(|line| match encoding {
+                Encoding::Byte => line.bytes().map(u32::from).collect(),
+                Encoding::Codepoint => line.chars().map(u32::from).collect(),
+            })
+            .collect();
+        let height = lines.len().max(1);
+        let width = lines
+            .iter()
+            .map(|line| line.len())
+            .max()
+            .unwrap_or(0)
+            .max(1);
+        let mut grid = Grid {
+            width: width as i64,
+            height: height as i64,
+            dense: vec![b' '; width * height],
+            sparse: HashMap::new(),
+            values: HashMap::new(),
+            min: (0, 0),
+            max: (width as i64 - 1, height as i64 - 1),
+        };
+        for (y, line) in lines.iter().enumerate() {
+            for (x, &c) in line.iter().enumerate() {
+                match u8::try_from(c) {
+                    Ok(b) => grid.dense[y * width + x] = b,
+                    Err(_) => {
+                        grid.dense[y * width + x] = 0;
+                        grid.values.insert((x as i64, y as i64), mode.int(c as i64));
+                    }
+                }
+            }
+        }
+        grid
+    }
+
+    /// dense_index returns where x/y is in the dense area, if it's in there.
+    pub fn dense_index(&self, x: i64, y: i64) -> Option<usize> {
+        if (0..self.width).contains(&x) && (0..self.height).contains(&y) {
+            Some((y * self.width + x) as usize)
+        } else {
+            None
+        }
+    }
+
+    /// dense_size returns the width and height of the dense area, which is the script's.
+    pub fn dense_size(&self) -> (i64, i64) {
+        (self.width, self.height)
+    }
+
+    /// dense_len returns how many cells the dense area holds.
+    pub fn dense_len(&self) -> usize {
+        self.dense.len()
+    }
+
+    /// get returns the byte in the cell at x/y, which is 0 if the cell holds some other value.
+    pub fn get(&self, x: i64, y: i64) -> u8 {
+        match self.dense_index(x, y) {
+            Some(i) => self.dense[i],
+            None => self.sparse.get(&(x, y)).copied().unwrap_or(0),
+        }
+    }
+
+    /// value returns the value in the cell at x/y if it isn't a byte.
+    pub fn value(&self, x: i64, y: i64) -> Option<&Value> {
+        if self.values.is_empty() {
+            return None;
+        }
+        self.values.get(&(x, y))
+    }
+
+    /// values returns every cell which holds a value other than a byte.
+    pub fn values(&self) -> impl Iterator<Item = &Value> {
+        self.values.values()
+    }
+
+    /// set changes the cell at x/y to v, growing the bounds to include it. It returns whether anything changed.
+    pub fn set(&mut self, x: i64, y: i64, v: Value) -> bool {
+        let byte = v.to_byte();
+        let changed = match byte {
+            Some(_) => self.values.remove(&(x, y)).is_some(),
+            None => self
+                .values
+                .insert((x, y), v.clone())
+                .is_none_or(|old| old != v),
+        };
+        let byte = byte.unwrap_or(0);
+        if !changed && self.get(x, y) == byte {
+            return false;
+        }
+        match self.dense_index(x, y) {
+            Some(i) => self.dense[i] = byte,
+            None if byte == 0 => _ = self.sparse.remove(&(x, y)),
+            None => _ = self.sparse.insert((x, y), byte),
+        }
+        self.min = (self.min.0.min(x), self.min.1.min(y));
+        self.max = (self.max.0.max(x), self.max.1.max(y));
+        true
+    }
+
+    /// origin returns the top left corner of the bounds.
+    pub fn origin(&self) -> (i64, i64) {
+        self.min
+    }
+
+    /// size returns the width and height of the bounds.
+    pub fn size(&self) -> (u64, u64) {
+        (
+            (self.max.0 - self.min.0) as u64 + 1,
+            (self.max.1 - self.min.1) as u64 + 1,
+        )
+    }
+
+    /// contains returns whether x/y is inside the bounds.
+    pub fn contains(&self, x: i64, y: i64) -> bool {
+        (self.min.0..=self.max.0).contains(&x) && (self.min.1..=self.max.1).contains(&y)
+    }
+
+    /// next returns the cell after x/y when swimming in dir, wrapping around the edges of the bounds.
+    pub fn next(&self, x: i64, y: i64, dir: Direction) -> (i64, i64) {
+        match dir {
+            Direction::Right if x >= self.max.0 => (self.min.0, y),
+            Direction::Right => (x + 1, y),
+            Direction::Down if y >= self.max.1 => (x, self.min.1),
+            Direction::Down => (x, y + 1),
+            Direction::Left if x <= self.min.0 => (self.max.0, y),
+            Direction::Left => (x - 1, y),
+            Direction::Up if y <= self.min.1 => (x, self.max.1),
+            Direction::Up => (x, y - 1),
+        }
+    }
+
+    /// check returns why the grid couldn't have been built by Grid.New and Grid.Set, if it couldn't have been.
+    pub fn check(&self) -> Result<(), &'static str> {
+        if self.width < 1 || self.height < 1 || self.dense.len() as i64 != self.width * self.height
+        {
+            return Err("the codebox's size doesn't match its cells");
+        }
+        let inside = |&(x, y): &(i64, i64)| self.contains(x, y);
+        if !inside(&(0, 0)) || !inside(&(self.width - 1, self.height - 1)) {
+            return Err("the codebox's bounds don't contain the script");
+        }
+        if !self.sparse.keys().chain(self.values.keys()).all(inside) {
+            return Err("the codebox's bounds don't contain every cell");
+        }
+        if self
+            .values
+            .iter()
+            .any(|(&(x, y), v)| v.to_byte().is_some() || self.get(x, y) != 0)
+        {
+            return Err("a byte is stored as a value");
+        }
+        Ok(())
+    }
+}
+
+/// cells (de)serializes a map of cells as a list of [x, y, value], sorted so the output is stable. Formats like
+/// JSON can't have tuples as map keys.
+mod cells {
+    use super::*;
+
+    pub fn serialize<S: Serializer, V: Serialize>(
+        cells: &HashMap<(i64, i64), V>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        let mut list: Vec<(i64, i64, &V)> = cells.iter().map(|(&(x, y), v)| (x, y, v)).collect();
+        list.sort_unstable_by_key(|&(x, y, _)| (x, y));
+        list.serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>, V: Deserialize<'de>>(
+        deserializer: D,
+    ) -> Result<HashMap<(i64, i64), V>, D::Error> {
+        let list = Vec::<(i64, i64, V)>::deserialize(deserializer)?;
+        Ok(list.into_iter().map(|(x, y, v)| ((x, y), v)).collect())
+    }
+}
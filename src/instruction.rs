@@ -0,0 +1,668 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use crate::Dialect::{self, Fish, Starfish};
+use Category::*;
+use Instruction::*;
+
+/// Instruction is a built-in instruction. Everything known about each one is in its InstructionInfo, which the
+/// interpreter, the linter, the language server and "starfish explain" all work from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum Instruction {
+    Nop,
+    Right,
+    Left,
+    Up,
+    Down,
+    Mirror,
+    BackMirror,
+    VerticalMirror,
+    HorizontalMirror,
+    TurnAround,
+    Random,
+    Hook,
+    Skip,
+    SkipIfZero,
+    Jump,
+    Halt,
+    DoubleQuote,
+    SingleQuote,
+    Push0,
+    Push1,
+    Push2,
+    Push3,
+    Push4,
+    Push5,
+    Push6,
+    Push7,
+    Push8,
+    Push9,
+    PushA,
+    PushB,
+    PushC,
+    PushD,
+    PushE,
+    PushF,
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Modulo,
+    Equal,
+    Greater,
+    Less,
+    Duplicate,
+    Drop,
+    Swap,
+    Rotate,
+    ShiftRight,
+    ShiftLeft,
+    Reverse,
+    Length,
+    NewStack,
+    CloseStack,
+    Register,
+    OutputChar,
+    OutputNumber,
+    Input,
+    Get,
+    Put,
+    Hour,
+    Minute,
+    Second,
+    Sleep,
+    DeepSea,
+    Surface,
+    File,
+    Call,
+    Return,
+    StackUp,
+    StackDown,
+}
+
+/// Category groups instructions by what they're for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Category {
+    /// Movement is the arrows, mirrors and other instructions which change the direction the ><> swims in.
+    Movement,
+    /// Flow is everything else which decides where the ><> swims next, or how it treats what it swims through.
+    Flow,
+    /// Literal pushes values written in the script.
+    Literal,
+    Arithmetic,
+    Stack,
+    /// Io is input, output and files.
+    Io,
+    /// Codebox reads and writes cells.
+    Codebox,
+    /// Time reads the clock and sleeps.
+    Time,
+}
+
+/// InstructionInfo describes a built-in instruction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InstructionInfo {
+    pub instruction: Instruction,
+    /// byte is the character which is the instruction in a script. The empty cell is a Nop too.
+    pub byte: u8,
+    /// name is a few words saying what the instruction is, like "add".
+    pub name: &'static str,
+    /// description says what the instruction does in a sentence or two.
+    pub description: &'static str,
+    pub category: Category,
+    /// effect is the instruction's stack effect in Forth's notation, like "x y -- x+y", or empty if it doesn't
+    /// touch the stack.
+    pub effect: &'static str,
+    /// pops and pushes are how many values the instruction takes off and puts on the current stack, or None when
+    /// that depends on what's on the stack or in the register.
+    pub pops: Option<u8>,
+    pub pushes: Option<u8>,
+    /// dialect is the first dialect with the instruction: Dialect::Fish for those in ><>, and
+    /// Dialect::Starfish for those *><> adds.
+    pub dialect: Dialect,
+    /// turns is set for the arrows, mirrors and other instructions which can change the direction the ><> swims in.
+    pub turns: bool,
+    /// deep_sea is set for the instructions which still run in deep-sea mode.
+    pub deep_sea: bool,
+}
+
+/// info is shorthand for building the table. Movement is what turns, and runs in deep-sea mode along with doing
+/// nothing and leaving deep-sea mode.
+#[allow(clippy::too_many_arguments)]
+const fn info(
+    instruction: Instruction,
+    byte: u8,
+    name: &'static str,
+    category: Category,
+    effect: &'static str,
+    (pops, pushes): (Option<u8>, Option<u8>),
+    dialect: Dialect,
+    description: &'static str,
+) -> InstructionInfo {
+    let turns = matches!(category, Movement);
+    InstructionInfo {
+        instruction,
+        byte,
+        name,
+        description,
+        category,
+        effect,
+        pops,
+        pushes,
+        dialect,
+        turns,
+        deep_sea: turns || matches!(instruction, Nop | Surface),
+    }
+}
+
+const NONE: (Option<u8>, Option<u8>) = (Some(0), Some(0));
+const VARIES: (Option<u8>, Option<u8>) = (None, None);
+
+/// counts is the pops and pushes of an instruction which always takes and leaves the same number of values.
+const fn counts(pops: u8, pushes: u8) -> (Option<u8>, Option<u8>) {
+    (Some(pops), Some(pushes))
+}
+
+/// TABLE describes every instruction, in the order Instruction declares them.
+static TABLE: [InstructionInfo; 69] = [
+    info(Nop, b' ', "nothing", Flow, "", NONE, Fish, "Does nothing."),
+    info(Right, b'>', "swim right", Movement, "", NONE, Fish, "Swims right."),
+    info(Left, b'<', "swim left", Movement, "", NONE, Fish, "Swims left."),
+    info(Up, b'^', "swim up", Movement, "", NONE, Fish, "Swims up."),
+    info(Down, b'v', "swim down", Movement, "", NONE, Fish, "Swims down."),
+    info(
+        Mirror,
+        b'/',
+        "mirror",
+        Movement,
+        "",
+        NONE,
+        Fish,
+        "Mirror: reflects the ><> as a / would.",
+    ),
+    info(
+        BackMirror,
+        b'\\',
+        "mirror",
+        Movement,
+        "",
+        NONE,
+        Fish,
+        "Mirror: reflects the ><> as a \\ would.",
+    ),
+    info(
+        VerticalMirror,
+        b'|',
+        "vertical mirror",
+        Movement,
+        "",
+        NONE,
+        Fish,
+        "Mirror: turns a ><> swimming left or right around.",
+    ),
+    info(
+        HorizontalMirror,
+        b'_',
+        "horizontal mirror",
+        Movement,
+        "",
+        NONE,
+        Fish,
+        "Mirror: turns a ><> swimming up or down around.",
+    ),
+    info(
+        TurnAround,
+        b'#',
+        "turn around",
+        Movement,
+        "",
+        NONE,
+        Fish,
+        "Mirror: turns the ><> around, whichever way it's swimming.",
+    ),
+    info(
+        Random,
+        b'x',
+        "random direction",
+        Movement,
+        "",
+        NONE,
+        Fish,
+        "Swims in a random direction.",
+    ),
+    info(
+        Hook,
+        b'`',
+        "hook",
+        Movement,
+        "",
+        NONE,
+        Starfish,
+        "Hook: turns a ><> swimming up or down the way it last swam horizontally. One swimming left or right \
+         turns down the first time, then up the next.",
+    ),
+    info(Skip, b'!', "skip", Flow, "", NONE, Fish, "Skips the next cell."),
+    info(
+        SkipIfZero,
+        b'?',
+        "skip if zero",
+        Flow,
+        "x --",
+        counts(1, 0),
+        Fish,
+        "Pops x, and skips the next cell if it's 0.",
+    ),
+    info(
+        Jump,
+        b'.',
+        "jump",
+        Flow,
+        "x y --",
+        counts(2, 0),
+        Fish,
+        "Pops y and x, then jumps to x, y.",
+    ),
+    info(Halt, b';', "halt", Flow, "", NONE, Fish, "Halts."),
+    info(
+        DoubleQuote,
+        b'"',
+        "string",
+        Literal,
+        "",
+        NONE,
+        Fish,
+        "Starts string mode, which pushes every cell as a value until the next \".",
+    ),
+    info(
+        SingleQuote,
+        b'\'',
+        "string",
+        Literal,
+        "",
+        NONE,
+        Fish,
+        "Starts string mode, which pushes every cell as a value until the next '.",
+    ),
+    info(Push0, b'0', "push 0", Literal, "-- 0", counts(0, 1), Fish, "Pushes 0."),
+    info(Push1, b'1', "push 1", Literal, "-- 1", counts(0, 1), Fish, "Pushes 1."),
+    info(Push2, b'2', "push 2", Literal, "-- 2", counts(0, 1), Fish, "Pushes 2."),
+    info(Push3, b'3', "push 3", Literal, "-- 3", counts(0, 1), Fish, "Pushes 3."),
+    info(Push4, b'4', "push 4", Literal, "-- 4", counts(0, 1), Fish, "Pushes 4."),
+    info(Push5, b'5', "push 5", Literal, "-- 5", counts(0, 1), Fish, "Pushes 5."),
+    info(Push6, b'6', "push 6", Literal, "-- 6", counts(0, 1), Fish, "Pushes 6."),
+    info(Push7, b'7', "push 7", Literal, "-- 7", counts(0, 1), Fish, "Pushes 7."),
+    info(Push8, b'8', "push 8", Literal, "-- 8", counts(0, 1), Fish, "Pushes 8."),
+    info(Push9, b'9', "push 9", Literal, "-- 9", counts(0, 1), Fish, "Pushes 9."),
+    info(PushA, b'a', "push 10", Literal, "-- 10", counts(0, 1), Fish, "Pushes 10."),
+    info(PushB, b'b', "push 11", Literal, "-- 11", counts(0, 1), Fish, "Pushes 11."),
+    info(PushC, b'c', "push 12", Literal, "-- 12", counts(0, 1), Fish, "Pushes 12."),
+    info(PushD, b'd', "push 13", Literal, "-- 13", counts(0, 1), Fish, "Pushes 13."),
+    info(PushE, b'e', "push 14", Literal, "-- 14", counts(0, 1), Fish, "Pushes 14."),
+    info(PushF, b'f', "push 15", Literal, "-- 15", counts(0, 1), Fish, "Pushes 15."),
+    info(
+        Add,
+        b'+',
+        "add",
+        Arithmetic,
+        "x y -- x+y",
+        counts(2, 1),
+        Fish,
+        "Pops y and x, then pushes x + y.",
+    ),
+    info(
+        Subtract,
+        b'-',
+        "subtract",
+        Arithmetic,
+        "x y -- x-y",
+        counts(2, 1),
+        Fish,
+        "Pops y and x, then pushes x - y.",
+    ),
+    info(
+        Multiply,
+        b'*',
+        "multiply",
+        Arithmetic,
+        "x y -- x*y",
+        counts(2, 1),
+        Fish,
+        "Pops y and x, then pushes x * y.",
+    ),
+    info(
+        Divide,
+        b',',
+        "divide",
+        Arithmetic,
+        "x y -- x/y",
+        counts(2, 1),
+        Fish,
+        "Pops y and x, then pushes x / y. Dividing by 0 is an error.",
+    ),
+    info(
+        Modulo,
+        b'%',
+        "modulo",
+        Arithmetic,
+        "x y -- x%y",
+        counts(2, 1),
+        Fish,
+        "Pops y and x, then pushes x modulo y, which is never negative.",
+    ),
+    info(
+        Equal,
+        b'=',
+        "equal",
+        Arithmetic,
+        "x y -- x=y",
+        counts(2, 1),
+        Fish,
+        "Pops y and x, then pushes 1 if they're equal, or 0.",
+    ),
+    info(
+        Greater,
+        b')',
+        "greater than",
+        Arithmetic,
+        "x y -- x>y",
+        counts(2, 1),
+        Fish,
+        "Pops y and x, then pushes 1 if x is greater than y, or 0.",
+    ),
+    info(
+        Less,
+        b'(',
+        "less than",
+        Arithmetic,
+        "x y -- x<y",
+        counts(2, 1),
+        Fish,
+        "Pops y and x, then pushes 1 if x is less than y, or 0.",
+    ),
+    info(
+        Duplicate,
+        b':',
+        "duplicate",
+        Stack,
+        "x -- x x",
+        counts(1, 2),
+        Fish,
+        "Duplicates the top value.",
+    ),
+    info(Drop, b'~', "drop", Stack, "x --", counts(1, 0), Fish, "Removes the top value."),
+    info(
+        Swap,
+        b'$',
+        "swap",
+        Stack,
+        "x y -- y x",
+        counts(2, 2),
+        Fish,
+        "Swaps the top two values.",
+    ),
+    info(
+        Rotate,
+        b'@',
+        "rotate",
+        Stack,
+        "x y z -- z x y",
+        counts(3, 3),
+        Fish,
+        "Moves the top value back two places.",
+    ),
+    info(
+        ShiftRight,
+        b'}',
+        "shift right",
+        Stack,
+        "",
+        VARIES,
+        Fish,
+        "Shifts the whole stack right, so the top value goes to the bottom.",
+    ),
+    info(
+        ShiftLeft,
+        b'{',
+        "shift left",
+        Stack,
+        "",
+        VARIES,
+        Fish,
+        "Shifts the whole stack left, so the bottom value goes to the top.",
+    ),
+    info(Reverse, b'r', "reverse", Stack, "", VARIES, Fish, "Reverses the stack."),
+    info(
+        Length,
+        b'l',
+        "length",
+        Stack,
+        "-- n",
+        counts(0, 1),
+        Fish,
+        "Pushes the length of the stack.",
+    ),
+    info(
+        NewStack,
+        b'[',
+        "new stack",
+        Stack,
+        "n --",
+        VARIES,
+        Fish,
+        "Pops n, then moves the top n values onto a new stack, which becomes the current one.",
+    ),
+    info(
+        CloseStack,
+        b']',
+        "close stack",
+        Stack,
+        "",
+        VARIES,
+        Fish,
+        "Closes the current stack, moving its values onto the top of the one below.",
+    ),
+    info(
+        Register,
+        b'&',
+        "register",
+        Stack,
+        "",
+        VARIES,
+        Fish,
+        "Pops a value into the register if it's empty, or pushes the value held in it.",
+    ),
+    info(
+        OutputChar,
+        b'o',
+        "output character",
+        Io,
+        "x --",
+        counts(1, 0),
+        Fish,
+        "Pops x and outputs it as a character.",
+    ),
+    info(
+        OutputNumber,
+        b'n',
+        "output number",
+        Io,
+        "x --",
+        counts(1, 0),
+        Fish,
+        "Pops x and outputs it as a number.",
+    ),
+    info(
+        Input,
+        b'i',
+        "input",
+        Io,
+        "-- x",
+        counts(0, 1),
+        Fish,
+        "Reads a character of input and pushes it, or -1 if there's none left.",
+    ),
+    info(
+        Get,
+        b'g',
+        "get",
+        Codebox,
+        "x y -- v",
+        counts(2, 1),
+        Fish,
+        "Pops y and x, then pushes the value of the cell at x, y.",
+    ),
+    info(
+        Put,
+        b'p',
+        "put",
+        Codebox,
+        "v x y --",
+        counts(3, 0),
+        Fish,
+        "Pops y, x and v, then writes v to the cell at x, y.",
+    ),
+    info(Hour, b'h', "hour", Time, "-- h", counts(0, 1), Starfish, "Pushes the current hour."),
+    info(
+        Minute,
+        b'm',
+        "minute",
+        Time,
+        "-- m",
+        counts(0, 1),
+        Starfish,
+        "Pushes the current minute.",
+    ),
+    info(
+        Second,
+        b's',
+        "second",
+        Time,
+        "-- s",
+        counts(0, 1),
+        Starfish,
+        "Pushes the current second.",
+    ),
+    info(
+        Sleep,
+        b'S',
+        "sleep",
+        Time,
+        "n --",
+        counts(1, 0),
+        Starfish,
+        "Pops n, then sleeps for n tenths of a second.",
+    ),
+    info(
+        DeepSea,
+        b'u',
+        "deep sea",
+        Flow,
+        "",
+        NONE,
+        Starfish,
+        "Starts deep-sea mode, which ignores everything but movement until an O.",
+    ),
+    info(Surface, b'O', "surface", Flow, "", NONE, Starfish, "Ends deep-sea mode."),
+    info(
+        File,
+        b'F',
+        "file",
+        Io,
+        "n --",
+        VARIES,
+        Starfish,
+        "Pops n, then n characters. The first F opens the file they name for i to read from; the next writes \
+         them to the file and closes it.",
+    ),
+    info(
+        Call,
+        b'C',
+        "call",
+        Flow,
+        "x y --",
+        counts(2, 0),
+        Starfish,
+        "Pops y and x, then calls x, y: the return address is kept on a new stack below the current one.",
+    ),
+    info(
+        Return,
+        b'R',
+        "return",
+        Flow,
+        "",
+        NONE,
+        Starfish,
+        "Returns to just after the C which called this code.",
+    ),
+    info(
+        StackUp,
+        b'I',
+        "stack up",
+        Stack,
+        "",
+        NONE,
+        Starfish,
+        "Moves to the stack above the current one.",
+    ),
+    info(
+        StackDown,
+        b'D',
+        "stack down",
+        Stack,
+        "",
+        NONE,
+        Starfish,
+        "Moves to the stack below the current one.",
+    ),
+];
+
+/// FROM_BYTE maps every byte to its instruction, built from TABLE.
+static FROM_BYTE: [Option<Instruction>; 256] = {
+    let mut from_byte = [None; 256];
+    let mut i = 0;
+    while i < TABLE.len() {
+        assert!(TABLE[i].instruction as usize == i, "TABLE is out of order");
+        from_byte[TABLE[i].byte as usize] = Some(TABLE[i].instruction);
+        i += 1;
+    }
+    from_byte[0] = Some(Nop);
+    from_byte
+};
+
+impl Instruction {
+    /// from_byte returns the instruction r is, if it's one. 0, the empty cell, is a Nop like " ".
+    pub fn from_byte(r: u8) -> Option<Instruction> {
+        FROM_BYTE[r as usize]
+    }
+
+    /// to_byte returns the character which is the instruction in a script.
+    pub fn to_byte(self) -> u8 {
+        self.info().byte
+    }
+
+    /// info returns everything known about the instruction.
+    pub fn info(self) -> &'static InstructionInfo {
+        &TABLE[self as usize]
+    }
+
+    /// all returns the description of every instruction, in the order Instruction declares them.
+    pub fn all() -> &'static [InstructionInfo] {
+        &TABLE
+    }
+
+    /// digit returns the value pushed by a hexadecimal digit.
+    pub fn digit(self) -> Option<u8> {
+        match self.info().category {
+            Category::Literal => (self.to_byte() as char).to_digit(16).map(|d| d as u8),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.info().name)
+    }
+}
@@ -0,0 +1,241 @@
+use std::collections::VecDeque;
+use std::io::{self, Write};
+use std::sync::mpsc::{channel, Receiver, Sender};
+#[cfg(not(target_arch = "wasm32"))]
+use std::sync::OnceLock;
+use std::sync::{Arc, Mutex};
+#[cfg(not(target_arch = "wasm32"))]
+use std::thread;
+use std::time::Duration;
+use std::{fmt, str};
+
+use chrono::{Local, NaiveTime};
+use serde::{Deserialize, Serialize};
+
+/// StarfishIo is everything a CodeBox needs from the outside world: input for "i", output for "o" and "n", and the
+/// sleeping and time-of-day used by "S", "h", "m" and "s".
+pub trait StarfishIo {
+    /// read_byte returns the next byte of input, or None if there isn't one available right now. It must not block.
+    fn read_byte(&mut self) -> Option<u8>;
+
+    /// write outputs buf.
+    fn write(&mut self, buf: &[u8]) -> io::Result<()>;
+
+    /// flush makes sure everything written so far is visible.
+    fn flush(&mut self) -> io::Result<()>;
+
+    /// sleep pauses the ><> for duration.
+    fn sleep(&mut self, duration: Duration);
+
+    /// now returns the current time of day.
+    fn now(&self) -> NaiveTime;
+}
+
+/// Clock is where "h", "m" and "s" get the time of day from, and what "S" sleeps on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum Clock {
+    /// The StarfishIo's clock, which for StdIo is the local time, with "S" really sleeping.
+    #[default]
+    System,
+    /// A clock which starts at the given time of day and only moves when the ><> sleeps. Sleeping just advances the
+    /// clock, without the StarfishIo or the caller waiting, so runs using it are reproducible.
+    Virtual(NaiveTime),
+}
+
+/// Encoding is how a CodeBox maps between text and the values in its cells and stacks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum Encoding {
+    /// Every byte of the script is a cell, and "i" reads single bytes. "o" writes the character whose code point
+    /// it's given, as UTF-8.
+    #[default]
+    Byte,
+    /// Every character of the script is a cell holding its code point. "i" decodes UTF-8 input into code points,
+    /// and "o" and "F" encode them, with values which aren't Unicode scalar values being errors.
+    Codepoint,
+}
+
+impl str::FromStr for Encoding {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Encoding, String> {
+        match s.to_ascii_lowercase().as_str() {
+            "byte" | "bytes" => Ok(Encoding::Byte),
+            "codepoint" | "codepoints" | "utf-8" | "utf8" => Ok(Encoding::Codepoint),
+            _ => Err(format!(
+                "unknown encoding {:?} (expected byte or codepoint)",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Encoding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Encoding::Byte => "byte",
+            Encoding::Codepoint => "codepoint",
+        })
+    }
+}
+
+/// STDIN receives the bytes of the process' stdin from the thread reading it, which starts the first time a StdIo
+/// reads input. Every StdIo shares it, so a StdIo which is never read from doesn't hold on to any input.
+#[cfg(not(target_arch = "wasm32"))]
+static STDIN: OnceLock<Mutex<Receiver<u8>>> = OnceLock::new();
+
+/// read_stdin starts the thread reading stdin, returning where its bytes arrive.
+#[cfg(not(target_arch = "wasm32"))]
+fn read_stdin() -> Mutex<Receiver<u8>> {
+    let (stdin_in, stdin_out) = channel();
+    thread::spawn(move || {
+        use std::io::Read;
+        let mut stdin = io::stdin();
+
+        loop {
+            let mut bs: [u8; 1] = [0];
+            match stdin.read(&mut bs) {
+                Ok(0) | Err(_) => return,
+                Ok(_) => {
+                    if stdin_in.send(bs[0]).is_err() {
+                        return;
+                    }
+                }
+            }
+        }
+    });
+    Mutex::new(stdin_out)
+}
+
+/// StdIo reads from the process' stdin and writes to its stdout. Stdin is read on a background thread, shared by
+/// every StdIo in the process and started by the first one to read input.
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StdIo;
+
+#[cfg(not(target_arch = "wasm32"))]
+impl StdIo {
+    pub fn new() -> StdIo {
+        StdIo
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl StarfishIo for StdIo {
+    fn read_byte(&mut self) -> Option<u8> {
+        let stdin = STDIN.get_or_init(read_stdin);
+        stdin.lock().unwrap().try_recv().ok()
+    }
+
+    fn write(&mut self, buf: &[u8]) -> io::Result<()> {
+        io::stdout().write_all(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        io::stdout().flush()
+    }
+
+    fn sleep(&mut self, duration: Duration) {
+        thread::sleep(duration);
+    }
+
+    fn now(&self) -> NaiveTime {
+        Local::now().time()
+    }
+}
+
+#[derive(Default)]
+struct MemoryBuffers {
+    input: VecDeque<u8>,
+    output: Vec<u8>,
+}
+
+/// MemoryIo keeps input and output in memory, and never really sleeps. Clones share the same buffers, so keep one
+/// around to feed input and inspect output after handing another to a CodeBox.
+#[derive(Clone, Default)]
+pub struct MemoryIo {
+    buffers: Arc<Mutex<MemoryBuffers>>,
+}
+
+impl MemoryIo {
+    /// new returns a MemoryIo which will read input and then report no more input.
+    pub fn new(input: &[u8]) -> MemoryIo {
+        let io = MemoryIo::default();
+        io.push_input(input);
+        io
+    }
+
+    /// push_input appends input for the ><> to read.
+    pub fn push_input(&self, input: &[u8]) {
+        self.buffers.lock().unwrap().input.extend(input);
+    }
+
+    /// input returns a copy of the input which hasn't been read yet.
+    pub fn input(&self) -> Vec<u8> {
+        self.buffers.lock().unwrap().input.iter().copied().collect()
+    }
+
+    /// output returns a copy of everything written so far.
+    pub fn output(&self) -> Vec<u8> {
+        self.buffers.lock().unwrap().output.clone()
+    }
+
+    /// take_output returns everything written so far and empties the output buffer.
+    pub fn take_output(&self) -> Vec<u8> {
+        std::mem::take(&mut self.buffers.lock().unwrap().output)
+    }
+}
+
+impl StarfishIo for MemoryIo {
+    fn read_byte(&mut self) -> Option<u8> {
+        self.buffers.lock().unwrap().input.pop_front()
+    }
+
+    fn write(&mut self, buf: &[u8]) -> io::Result<()> {
+        self.buffers.lock().unwrap().output.extend_from_slice(buf);
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn sleep(&mut self, _duration: Duration) {}
+
+    fn now(&self) -> NaiveTime {
+        Local::now().time()
+    }
+}
+
+/// ChannelIo takes its input from a channel, which is how input is injected on wasm. It doesn't write or sleep
+/// anywhere: the caller is expected to act on the Output and Sleep steps returned by CodeBox.Swim instead.
+pub struct ChannelIo {
+    input: Receiver<u8>,
+}
+
+impl ChannelIo {
+    /// new returns a ChannelIo along with the Sender used to inject its input.
+    pub fn new() -> (ChannelIo, Sender<u8>) {
+        let (sender, input) = channel();
+        (ChannelIo { input }, sender)
+    }
+}
+
+impl StarfishIo for ChannelIo {
+    fn read_byte(&mut self) -> Option<u8> {
+        self.input.try_recv().ok()
+    }
+
+    fn write(&mut self, _buf: &[u8]) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn sleep(&mut self, _duration: Duration) {}
+
+    fn now(&self) -> NaiveTime {
+        Local::now().time()
+    }
+}
@@ -1,86 +1,208 @@
 // Spec: https://esolangs.org/wiki/Starfish
 use std::error::Error;
-#[cfg(not(target_arch = "wasm32"))]
-use std::fs::File;
-#[cfg(not(target_arch = "wasm32"))]
-use std::io;
-#[cfg(not(target_arch = "wasm32"))]
-use std::io::Read;
-use std::io::{stdout, Write};
-use std::process;
-#[cfg(target_arch = "wasm32")]
-use std::sync::mpsc::Sender;
-use std::sync::mpsc::{channel, Receiver};
-#[cfg(not(target_arch = "wasm32"))]
-use std::thread;
-use std::{char, panic, str};
+use std::fmt;
+use std::io::{Read, Write};
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+use std::{char, str};
 
 use chrono::prelude::*;
-use rand::Rng;
+use num_bigint::BigInt;
+use num_traits::ToPrimitive;
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use serde::{Deserialize, Serialize};
 
-fn crash() {
-    println!("something smells fishy...");
-    process::exit(1);
-}
+#[cfg(feature = "capi")]
+pub mod capi;
+mod custom;
+mod error;
+mod explain;
+mod fs;
+mod grid;
+mod instruction;
+mod io;
+mod lint;
+mod observer;
+mod profile;
+mod run;
+mod snapshot;
+mod trace;
+mod value;
+#[cfg(feature = "wasm")]
+mod wasm;
+
+pub use custom::{Action, CustomInstruction, Registry};
+pub use error::{Location, StarfishError};
+pub use explain::{Annotation, Explanation};
+pub use fs::{DenyFs, FileSystem, MemoryFs};
+#[cfg(not(target_arch = "wasm32"))]
+pub use fs::{RealFs, RootFs};
+use grid::Grid;
+pub use instruction::{Category, Instruction, InstructionInfo};
+#[cfg(not(target_arch = "wasm32"))]
+pub use io::StdIo;
+pub use io::{ChannelIo, Clock, Encoding, MemoryIo, StarfishIo};
+pub use lint::{Diagnostic, LintKind, Severity};
+pub use observer::Observer;
+pub use profile::Profile;
+pub use run::{ExitReason, Limits, RunReport};
+pub use snapshot::Snapshot;
+use trace::Trace;
+pub use value::{ArithmeticError, NumericMode, Value};
+#[cfg(feature = "wasm")]
+pub use wasm::{Report, StarfishVM};
+
+/// VIEW_SIZE is the most cells across or down CodeBox.View covers, so printing a codebox whose bounds "p" has
+/// stretched far doesn't take forever.
+pub const VIEW_SIZE: u64 = 256;
 
-#[derive(PartialEq)]
-enum Direction {
+/// Direction is the way the ><> is swimming.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Direction {
     Right,
     Down,
     Left,
     Up,
 }
 
-impl Direction {
-    fn from_i32(val: i32) -> Direction {
-        match val {
-            0 => Direction::Right,
-            1 => Direction::Down,
-            2 => Direction::Left,
-            3 => Direction::Up,
-            _ => panic!("Invalid direction (valid values are 0-3): {}", val),
+/// mirror returns the direction the ><> swims in after hitting the arrow or mirror r while swimming in dir, and
+/// what was_left becomes if the instruction changes it. Anything else leaves the ><> swimming the way it was.
+fn mirror(r: u8, dir: Direction) -> (Direction, Option<bool>) {
+    use Direction::*;
+    match (r, dir) {
+        (b'>', _) | (b'|', Left) | (b'#', Left) | (b'/', Up) | (b'\\', Down) => {
+            (Right, Some(false))
+        }
+        (b'<', _) | (b'|', Right) | (b'#', Right) | (b'/', Down) | (b'\\', Up) => {
+            (Left, Some(true))
+        }
+        (b'v', _) | (b'_', Up) | (b'#', Up) | (b'/', Left) | (b'\\', Right) => (Down, None),
+        (b'^', _) | (b'_', Down) | (b'#', Down) | (b'/', Right) | (b'\\', Left) => (Up, None),
+        _ => (dir, None),
+    }
+}
+
+/// to_char returns v as a character, if it's a whole number which is a Unicode scalar value.
+fn to_char(v: &Value) -> Option<char> {
+    let f = v.to_f64();
+    if f.fract() != 0.0 || !(0.0..=char::MAX as u32 as f64).contains(&f) {
+        return None;
+    }
+    char::from_u32(f as u32)
+}
+
+/// Dialect is the flavour of the language a CodeBox understands.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum Dialect {
+    /// Plain ><>. The *><> instructions are invalid, like any other unknown instruction.
+    Fish,
+    /// *><>, the default.
+    #[default]
+    Starfish,
+    /// *><>, but "[" and "]" reverse the stack they move, as the old fishinterpreter.com interpreter did.
+    FishInterpreterCom,
+}
+
+impl Dialect {
+    /// allows returns whether r is an instruction in the dialect, assuming it's an instruction in *><>.
+    pub fn allows(self, r: u8) -> bool {
+        Instruction::from_byte(r).is_none_or(|i| self.has(i))
+    }
+
+    /// has returns whether instruction is in the dialect.
+    pub fn has(self, instruction: Instruction) -> bool {
+        self != Dialect::Fish || instruction.info().dialect == Dialect::Fish
+    }
+}
+
+impl str::FromStr for Dialect {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Dialect, String> {
+        match s.to_ascii_lowercase().as_str() {
+            "fish" | "><>" => Ok(Dialect::Fish),
+            "starfish" | "*><>" => Ok(Dialect::Starfish),
+            "fishinterpreter.com" | "fishinterpretercom" => Ok(Dialect::FishInterpreterCom),
+            _ => Err(format!(
+                "unknown dialect {:?} (expected fish, starfish or fishinterpreter.com)",
+                s
+            )),
         }
     }
 }
 
-/// Stack is a type representing a stack in *><>. It holds the stack values in s, as well as a register. The
-/// register may contain data, but will only be considered filled if filled_register is also true.
+impl fmt::Display for Dialect {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Dialect::Fish => "fish",
+            Dialect::Starfish => "starfish",
+            Dialect::FishInterpreterCom => "fishinterpreter.com",
+        })
+    }
+}
+
+/// Step is the result of a successful CodeBox.Swim.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Step {
+    /// The ><> executed an instruction which produced nothing for the caller.
+    Continue,
+    /// The ><> output a string ("o" or "n"), which has already been written to its StarfishIo.
+    Output(String),
+    /// The ><> slept for the given number of milliseconds ("S"), which is never negative or NaN. StarfishIo backends
+    /// which can't block, like ChannelIo, leave the actual waiting to the caller. Sleeping on a Clock::Virtual returns Step::Continue
+    /// instead, as there's nothing to wait for.
+    Sleep(f64),
+    /// The ><> executed ";".
+    Halt,
+}
+
+/// Stack is a type representing a stack in *><>. It holds the stack values in s, as well as a register, which is
+/// None while empty.
+///
+/// Operations which need more values than the stack holds return None and leave the stack untouched.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Stack {
-    pub s: Vec<f64>,
-    register: f64,
-    filled_register: bool,
+    pub s: Vec<Value>,
+    register: Option<Value>,
 }
 
 impl Stack {
-    pub fn new(s: Option<Vec<f64>>) -> Stack {
-        let new_stack: Vec<f64>;
-        match s {
-            Some(inner) => new_stack = inner,
-            None => new_stack = Vec::new(),
-        }
+    pub fn new(s: Option<Vec<Value>>) -> Stack {
         Stack {
-            s: new_stack,
-            register: 0.0,
-            filled_register: false,
+            s: s.unwrap_or_default(),
+            register: None,
         }
     }
 
+    /// from_string parses an initial stack. Whole numbers are parsed exactly, while numbers with a decimal point
+    /// are parsed as floats.
     pub fn from_string(str_stack: &str) -> Result<Stack, Box<dyn Error>> {
-        let mut s: Vec<f64> = Vec::new();
+        let mut s: Vec<Value> = Vec::new();
         let mut str_mode: u8 = 0;
         let mut cur_str = String::new();
 
+        fn parse(num: &str) -> Result<Value, Box<dyn Error>> {
+            if num.contains('.') {
+                return Ok(Value::Float(num.parse()?));
+            }
+            let i: BigInt = num.parse()?;
+            Ok(match i.to_i64() {
+                Some(i) => Value::Int(i),
+                None => Value::BigInt(Box::new(i)),
+            })
+        }
+
         for b in str_stack.bytes() {
             if str_mode != 0 && b != str_mode {
-                s.push(b as f64);
+                s.push(Value::Int(b as i64));
                 continue;
             }
             match b {
                 b' ' => {
-                    if cur_str.len() > 0 {
-                        let f: f64 = cur_str.parse()?;
+                    if !cur_str.is_empty() {
+                        s.push(parse(&cur_str)?);
                         cur_str = String::new();
-                        s.push(f);
                     }
                 }
                 b'\'' | b'"' => {
@@ -97,43 +219,47 @@ impl Stack {
             }
         }
 
-        if cur_str.len() > 0 {
-            let f: f64 = cur_str.parse()?;
-            s.push(f);
+        if !cur_str.is_empty() {
+            s.push(parse(&cur_str)?);
         }
 
-        return Ok(Stack::new(Some(s)));
+        Ok(Stack::new(Some(s)))
     }
 
-    /// output information about the stack
-    pub fn to_string(&self) -> String {
-        return format!("{:?}", self.s);
+    /// convert changes every value on the stack, and in the register, to mode.
+    pub fn convert(&mut self, mode: NumericMode) {
+        for v in self.s.iter_mut() {
+            *v = mode.convert(v.clone());
+        }
+        if let Some(r) = self.register.take() {
+            self.register = Some(mode.convert(r));
+        }
     }
 
     /// push r to the end of the stack
-    pub fn push(&mut self, r: f64) {
+    pub fn push(&mut self, r: Value) {
         self.s.push(r);
     }
 
     /// pop a value from the end of the stack, and return it
-    pub fn pop(&mut self) -> f64 {
-        self.s.pop().unwrap()
+    pub fn pop(&mut self) -> Option<Value> {
+        self.s.pop()
     }
 
     /// register implements "&".
-    pub fn register(&mut self) {
-        if self.filled_register {
-            self.s.push(self.register);
-            self.filled_register = false;
-        } else {
-            self.register = self.s.pop().unwrap();
-            self.filled_register = true;
+    pub fn register(&mut self) -> Option<()> {
+        match self.register.take() {
+            Some(r) => self.s.push(r),
+            None => self.register = Some(self.s.pop()?),
         }
+        Some(())
     }
 
     /// extend implements ":".
-    pub fn extend(&mut self) {
-        self.s.push(self.s[self.s.len() - 1]);
+    pub fn extend(&mut self) -> Option<()> {
+        let end = self.s.last()?.clone();
+        self.s.push(end);
+        Some(())
     }
 
     /// reverse implements "r".
@@ -142,529 +268,826 @@ impl Stack {
     }
 
     /// swap_two implements "$".
-    pub fn swap_two(&mut self) {
+    pub fn swap_two(&mut self) -> Option<()> {
         let len = self.s.len();
+        if len < 2 {
+            return None;
+        }
         self.s.swap(len - 2, len - 1);
+        Some(())
     }
 
     /// swap_three implements "@": with [1,2,3,4], calling "@" results in [1,4,2,3].
-    pub fn swap_three(&mut self) {
-        // Is there a better way to do this?
+    pub fn swap_three(&mut self) -> Option<()> {
         let len = self.s.len();
-        let end = self.s[len - 1];
-        self.s[len - 1] = self.s[len - 2];
-        self.s[len - 2] = self.s[len - 3];
-        self.s[len - 3] = end;
+        if len < 3 {
+            return None;
+        }
+        self.s[len - 3..].rotate_right(1);
+        Some(())
     }
 
     /// shift_right implements "}".
-    pub fn shift_right(&mut self) {
-        let end = self.s.pop().unwrap();
+    pub fn shift_right(&mut self) -> Option<()> {
+        let end = self.s.pop()?;
         self.s.insert(0, end);
+        Some(())
     }
 
     /// shift_left implements "{".
-    pub fn shift_left(&mut self) {
-        let beg = self.s[0];
-        self.s.remove(0);
+    pub fn shift_left(&mut self) -> Option<()> {
+        if self.s.is_empty() {
+            return None;
+        }
+        let beg = self.s.remove(0);
         self.s.push(beg);
+        Some(())
+    }
+
+    /// register_value returns what's stored in the register, or None if the register is empty.
+    pub fn register_value(&self) -> Option<&Value> {
+        self.register.as_ref()
     }
 
     /// get_bytes removes c values from the stack, then returns them as a byte vector.
-    pub fn get_bytes(&mut self, count: usize) -> Vec<u8> {
+    pub fn get_bytes(&mut self, count: usize) -> Option<Vec<u8>> {
         let len = self.s.len();
-        let vals = self.s.drain(len - count..len).as_slice().to_vec();
-        let mut out = vec![0; vals.len()];
-        for i in 0..vals.len() {
-            out[i] = vals[i] as u8;
+        if count > len {
+            return None;
         }
-        out
+        Some(
+            self.s
+                .drain(len - count..)
+                .map(|v| v.to_f64() as u8)
+                .collect(),
+        )
+    }
+}
+
+impl fmt::Display for Stack {
+    /// output information about the stack. Floats keep their fractional part, so this matches how a Vec<f64>
+    /// has always been printed.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("[")?;
+        for (i, v) in self.s.iter().enumerate() {
+            if i > 0 {
+                f.write_str(", ")?;
+            }
+            match v {
+                Value::Float(v) => write!(f, "{:?}", v)?,
+                v => write!(f, "{}", v)?,
+            }
+        }
+        f.write_str("]")
     }
 }
 
 /// CodeBox is an object. It contains a *><> program complete with a stack, and is typically run in steps via CodeBox.Swim.
 pub struct CodeBox {
-    f_x: usize,
-    f_y: usize,
-    width: usize,
-    height: usize,
+    f_x: i64,
+    f_y: i64,
     f_dir: Direction,
     was_left: bool,
     escaped_hook: bool,
-    code_box: Vec<Vec<u8>>,
+    grid: Grid,
     stacks: Vec<Stack>,
     p: usize, // Used to keep track of current stack
     string_mode: u8,
-    compatibility_mode: bool,
+    dialect: Dialect,
+    numeric_mode: NumericMode,
+    encoding: Encoding,
     deep_sea: bool,
-    #[cfg(not(target_arch = "wasm32"))]
-    file: Option<File>,
-    #[cfg(not(target_arch = "wasm32"))]
+    fs: Box<dyn FileSystem>,
+    // The file opened by "F", and its path.
+    file: Option<Box<dyn Read>>,
     file_path: String,
-    stdin_out: Receiver<u8>,
-    #[cfg(target_arch = "wasm32")]
-    stdin_in: Sender<u8>,
+    io: Box<dyn StarfishIo>,
+    registry: Registry,
+    observer: Option<Box<dyn Observer>>,
+    clock: Clock,
+    rng: ChaCha8Rng,
+    trace_cache: bool,
+    // Compiled traces, indexed by CodeBox.trace_index. Allocated the first time one is needed.
+    traces: Vec<Option<Rc<Trace>>>,
+    // generation counts changes to the codebox. Traces compiled in an older generation are stale.
+    generation: u64,
+    // Scratch space for CodeBox.compile_trace, the same size as traces.
+    trace_marks: Vec<u64>,
+    trace_mark: u64,
+    // How many more bytes of output may be written to io, while CodeBox.RunWith enforces Limits.max_output.
+    output_budget: Option<usize>,
+    // The bytes read so far of a UTF-8 character, with Encoding::Codepoint, while waiting for the rest of them.
+    partial_char: Vec<u8>,
+    // When CodeBox.RunWith has to stop by, while it enforces Limits.max_time. "S" doesn't sleep past it.
+    deadline: Option<Instant>,
 }
 
-impl CodeBox {
-    /// new returns a new CodeBox. "script" should be a complete *><> script, "stack" should
-    /// be the initial stack, and compatibility_mode should be set if old fishinterpreter.com behaviour is needed.
-    pub fn new(script: &str, stack: Stack, compatibility_mode: bool) -> CodeBox {
-        let height = script.lines().count();
-        let mut width = 0;
-        for line in script.lines() {
-            let len = line.len();
-            if len > width {
-                width = len;
-            }
-        }
-        let mut lines = script.lines();
-        let mut code_box = vec![vec![b' '; width]; height];
-        for i in 0..code_box.len() {
-            let line = lines.next().unwrap().as_bytes();
-            for ii in 0..line.len() {
-                code_box[i][ii] = line[ii];
-            }
-        }
+/// CodeBoxBuilder configures and creates a CodeBox. It's returned by CodeBox.Builder.
+pub struct CodeBoxBuilder<'a> {
+    script: &'a str,
+    stack: Option<Stack>,
+    dialect: Dialect,
+    numeric_mode: NumericMode,
+    encoding: Encoding,
+    io: Option<Box<dyn StarfishIo>>,
+    fs: Option<Box<dyn FileSystem>>,
+    registry: Registry,
+    observer: Option<Box<dyn Observer>>,
+    clock: Clock,
+    seed: Option<u64>,
+    trace_cache: bool,
+}
 
-        let mut stacks = Vec::new();
-        stacks.push(stack);
+impl CodeBoxBuilder<'_> {
+    /// stack sets the initial stack. Defaults to an empty stack.
+    pub fn stack(mut self, stack: Stack) -> Self {
+        self.stack = Some(stack);
+        self
+    }
 
-        let (stdin_in, stdin_out) = channel();
-        #[cfg(not(target_arch = "wasm32"))]
-        {
-            thread::spawn(move || {
-                let mut stdin = io::stdin();
-
-                loop {
-                    let mut bs: [u8; 1] = [0];
-                    let read_res = stdin.read(&mut bs);
-                    match read_res {
-                        Ok(v) => {
-                            if v > 0 {
-                                _ = stdin_in.send(bs[0]);
-                            }
-                        }
-                        Err(_e) => {
-                            return;
-                        }
-                    }
-                }
-            });
-        }
+    /// dialect sets which flavour of the language is understood. Defaults to Dialect::Starfish.
+    pub fn dialect(mut self, dialect: Dialect) -> Self {
+        self.dialect = dialect;
+        self
+    }
 
-        panic::set_hook(Box::new(|_| {
-            crash();
-        }));
+    /// numeric_mode sets how values on the stacks are represented. Defaults to NumericMode::Float. The initial
+    /// stack is converted to the mode.
+    pub fn numeric_mode(mut self, numeric_mode: NumericMode) -> Self {
+        self.numeric_mode = numeric_mode;
+        self
+    }
+
+    /// encoding sets how the script, input and output are mapped to values. Defaults to Encoding::Byte.
+    pub fn encoding(mut self, encoding: Encoding) -> Self {
+        self.encoding = encoding;
+        self
+    }
+
+    /// io sets where input comes from and output goes to. Defaults to StdIo (ChannelIo with no input on wasm).
+    pub fn io(mut self, io: impl StarfishIo + 'static) -> Self {
+        self.io = Some(Box::new(io));
+        self
+    }
+
+    /// file_system sets where "F" opens and writes files. Defaults to RealFs (DenyFs on wasm).
+    pub fn file_system(mut self, fs: impl FileSystem + 'static) -> Self {
+        self.fs = Some(Box::new(fs));
+        self
+    }
+
+    /// registry sets the custom instructions understood on top of the built-in ones. Defaults to none.
+    pub fn registry(mut self, registry: Registry) -> Self {
+        self.registry = registry;
+        self
+    }
+
+    /// observer attaches an Observer to watch the CodeBox execute. Defaults to none.
+    pub fn observer(mut self, observer: impl Observer + 'static) -> Self {
+        self.observer = Some(Box::new(observer));
+        self
+    }
+
+    /// clock sets where the time of day comes from and how "S" sleeps. Defaults to Clock::System.
+    pub fn clock(mut self, clock: Clock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// seed makes "x" choose directions from a random number generator seeded with seed, so runs are reproducible.
+    /// By default the generator is seeded randomly.
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    /// trace_cache sets whether CodeBox.Run executes runs of instructions from a cache of decoded traces, rather
+    /// than decoding every instruction as it swims. Defaults to true; the results are the same either way.
+    pub fn trace_cache(mut self, trace_cache: bool) -> Self {
+        self.trace_cache = trace_cache;
+        self
+    }
+
+    /// build returns the configured CodeBox.
+    pub fn build(self) -> CodeBox {
+        let mut stack = self.stack.unwrap_or_else(|| Stack::new(None));
+        stack.convert(self.numeric_mode);
+        let stacks = vec![stack];
+
+        #[cfg(not(target_arch = "wasm32"))]
+        let io = self.io.unwrap_or_else(|| Box::new(StdIo::new()));
+        #[cfg(target_arch = "wasm32")]
+        let io = self.io.unwrap_or_else(|| Box::new(ChannelIo::new().0));
+        #[cfg(not(target_arch = "wasm32"))]
+        let fs = self.fs.unwrap_or_else(|| Box::new(RealFs));
+        #[cfg(target_arch = "wasm32")]
+        let fs = self.fs.unwrap_or_else(|| Box::new(DenyFs));
 
         CodeBox {
             f_x: 0,
             f_y: 0,
-            width: width,
-            height: height,
             f_dir: Direction::Right,
             was_left: false,
             escaped_hook: false,
-            code_box: code_box,
-            stacks: stacks,
+            grid: Grid::new(self.script, self.encoding, self.numeric_mode),
+            stacks,
             p: 0,
             string_mode: 0,
-            compatibility_mode: compatibility_mode,
+            dialect: self.dialect,
+            numeric_mode: self.numeric_mode,
+            encoding: self.encoding,
             deep_sea: false,
-            #[cfg(not(target_arch = "wasm32"))]
+            fs,
             file: None,
-            #[cfg(not(target_arch = "wasm32"))]
             file_path: String::new(),
-            stdin_out: stdin_out,
-            #[cfg(target_arch = "wasm32")]
-            stdin_in: stdin_in,
+            io,
+            registry: self.registry,
+            observer: self.observer,
+            clock: self.clock,
+            rng: match self.seed {
+                Some(seed) => ChaCha8Rng::seed_from_u64(seed),
+                None => ChaCha8Rng::from_entropy(),
+            },
+            trace_cache: self.trace_cache,
+            traces: Vec::new(),
+            generation: 0,
+            trace_marks: Vec::new(),
+            trace_mark: 0,
+            output_budget: None,
+            partial_char: Vec::new(),
+            deadline: None,
         }
     }
+}
+
+impl CodeBox {
+    /// new returns a new CodeBox using StdIo. "script" should be a complete *><> script, "stack" should
+    /// be the initial stack, and dialect the flavour of the language to understand.
+    pub fn new(script: &str, stack: Stack, dialect: Dialect) -> CodeBox {
+        CodeBox::builder(script)
+            .stack(stack)
+            .dialect(dialect)
+            .build()
+    }
 
-    #[cfg(target_arch = "wasm32")]
-    /// inject_input acts like stdin, inserting whatever Vec<u8> is passed
-    pub fn inject_input(&self, inp: Vec<u8>) {
-        for i in inp {
-            _ = self.stdin_in.send(i);
+    /// builder returns a CodeBoxBuilder for "script", a complete *><> script.
+    pub fn builder(script: &str) -> CodeBoxBuilder<'_> {
+        CodeBoxBuilder {
+            script,
+            stack: None,
+            dialect: Dialect::default(),
+            numeric_mode: NumericMode::default(),
+            encoding: Encoding::default(),
+            io: None,
+            fs: None,
+            registry: Registry::default(),
+            observer: None,
+            clock: Clock::default(),
+            seed: None,
+            trace_cache: true,
         }
     }
 
-    /// shift changes the fish's x/y coordinates based on CodeBox.f_dir.
-    pub fn shift(&mut self) {
-        match &self.f_dir {
-            Direction::Right => {
-                self.f_x += 1;
-                if self.f_x >= self.width {
-                    self.f_x = 0;
-                }
-            }
-            Direction::Down => {
-                self.f_y += 1;
-                if self.f_y >= self.height {
-                    self.f_y = 0;
-                }
-            }
-            Direction::Left => {
-                if self.f_x > 0 {
-                    self.f_x -= 1;
-                } else {
-                    self.f_x = self.width - 1;
-                }
-            }
-            Direction::Up => {
-                if self.f_y > 0 {
-                    self.f_y -= 1;
-                } else {
-                    self.f_y = self.height - 1;
-                }
-            }
+    /// read_byte reads the next byte of input, from the file opened with "F" if there is one.
+    fn read_byte(&mut self) -> Option<u8> {
+        if let Some(file) = &mut self.file {
+            let mut bs = [0];
+            return match file.read(&mut bs) {
+                Ok(1) => Some(bs[0]),
+                _ => None,
+            };
         }
+        self.io.read_byte()
     }
 
-    /// exe executes the instruction the ><> is currently on top of. It returns the string it intends to output (None if none) and true when it executes ";".
-    /// It also returns the time it should sleep for.
-    pub fn exe(&mut self, r: u8) -> (Option<String>, bool, f64) {
-        match r {
-            b' ' => return (None, false, 0.0),
-            b'>' => {
-                self.f_dir = Direction::Right;
-                self.was_left = false;
-                return (None, false, 0.0);
-            }
-            b'v' => {
-                self.f_dir = Direction::Down;
-                return (None, false, 0.0);
+    /// read_char reads the next character of UTF-8 input, like CodeBox.read_byte. A character whose bytes haven't
+    /// all arrived yet is kept until they have, with no input read in the meantime. Bytes which aren't UTF-8, and a
+    /// character cut short by the end of a file opened with "F", are an error.
+    fn read_char(&mut self) -> Result<Option<char>, StarfishError> {
+        loop {
+            let len = match self.partial_char.first() {
+                None => 1,
+                Some(0x00..=0x7f) => 1,
+                Some(0xc2..=0xdf) => 2,
+                Some(0xe0..=0xef) => 3,
+                Some(0xf0..=0xf4) => 4,
+                Some(_) => 0,
+            };
+            if !self.partial_char.is_empty() && self.partial_char.len() >= len {
+                break;
             }
-            b'<' => {
-                self.f_dir = Direction::Left;
-                self.was_left = true;
-                return (None, false, 0.0);
-            }
-            b'^' => {
-                self.f_dir = Direction::Up;
-                return (None, false, 0.0);
-            }
-            b'|' => {
-                if self.f_dir == Direction::Right {
-                    self.f_dir = Direction::Left;
-                    self.was_left = true;
-                } else if self.f_dir == Direction::Left {
-                    self.f_dir = Direction::Right;
-                    self.was_left = false;
-                }
-                return (None, false, 0.0);
-            }
-            b'_' => {
-                if self.f_dir == Direction::Down {
-                    self.f_dir = Direction::Up;
-                } else if self.f_dir == Direction::Up {
-                    self.f_dir = Direction::Down;
-                }
-                return (None, false, 0.0);
-            }
-            b'#' => {
-                match self.f_dir {
-                    Direction::Right => {
-                        self.f_dir = Direction::Left;
-                        self.was_left = true;
-                    }
-                    Direction::Down => self.f_dir = Direction::Up,
-                    Direction::Left => {
-                        self.f_dir = Direction::Right;
-                        self.was_left = false;
-                    }
-                    Direction::Up => self.f_dir = Direction::Down,
-                }
-                return (None, false, 0.0);
-            }
-            b'/' => {
-                match self.f_dir {
-                    Direction::Right => self.f_dir = Direction::Up,
-                    Direction::Down => {
-                        self.f_dir = Direction::Left;
-                        self.was_left = true;
-                    }
-                    Direction::Left => self.f_dir = Direction::Down,
-                    Direction::Up => {
-                        self.f_dir = Direction::Right;
-                        self.was_left = false;
+            match self.read_byte() {
+                Some(b) => {
+                    self.partial_char.push(b);
+                    // Only continuation bytes can follow the first.
+                    if self.partial_char.len() > 1 && b & 0xc0 != 0x80 {
+                        break;
                     }
                 }
-                return (None, false, 0.0);
+                None if self.file.is_some() && !self.partial_char.is_empty() => break,
+                None => return Ok(None),
             }
-            b'\\' => {
-                match self.f_dir {
-                    Direction::Right => self.f_dir = Direction::Down,
-                    Direction::Down => {
-                        self.f_dir = Direction::Right;
-                        self.was_left = false;
-                    }
-                    Direction::Left => self.f_dir = Direction::Up,
-                    Direction::Up => {
-                        self.f_dir = Direction::Left;
-                        self.was_left = true;
-                    }
-                }
-                return (None, false, 0.0);
+        }
+        let bytes = std::mem::take(&mut self.partial_char);
+        match str::from_utf8(&bytes) {
+            Ok(s) => Ok(s.chars().next()),
+            Err(_) => Err(StarfishError::InvalidInput(bytes, self.location())),
+        }
+    }
+
+    /// pop_text removes count values from the current stack and returns them as bytes, encoded as the
+    /// CodeBox.Encoding says.
+    fn pop_text(&mut self, count: usize) -> Result<Vec<u8>, StarfishError> {
+        if self.observer.is_none() {
+            return self.pop_text_unobserved(count);
+        }
+        let s = &self.stacks[self.p].s;
+        let popped = s[s.len().saturating_sub(count)..].to_vec();
+        let text = self.pop_text_unobserved(count)?;
+        if let Some(observer) = &mut self.observer {
+            for v in popped.iter().rev() {
+                observer.on_stack_pop(self.p, v);
             }
-            b'x' => {
-                self.f_dir = Direction::from_i32(rand::thread_rng().gen_range(0..4));
-                if self.f_dir == Direction::Right {
-                    self.was_left = false;
-                } else {
-                    self.was_left = true;
+        }
+        Ok(text)
+    }
+
+    /// pop_text_unobserved implements CodeBox.pop_text, apart from calling the Observer.
+    fn pop_text_unobserved(&mut self, count: usize) -> Result<Vec<u8>, StarfishError> {
+        if self.encoding == Encoding::Byte {
+            return self.stacks[self.p]
+                .get_bytes(count)
+                .ok_or_else(|| self.underflow());
+        }
+        let len = self.stacks[self.p].s.len();
+        let start = len.checked_sub(count).ok_or_else(|| self.underflow())?;
+        let mut text = String::new();
+        for v in &self.stacks[self.p].s[start..] {
+            let c = to_char(v)
+                .ok_or_else(|| StarfishError::InvalidCharacter(v.to_f64(), self.location()))?;
+            text.push(c);
+        }
+        self.stacks[self.p].s.truncate(start);
+        Ok(text.into_bytes())
+    }
+
+    /// output writes s to the ><>'s StarfishIo and returns it as a Step. Only as much of s as the output budget
+    /// allows is written.
+    fn output(&mut self, s: String) -> Result<Step, StarfishError> {
+        if let Some(observer) = &mut self.observer {
+            observer.on_output(&s);
+        }
+        let mut bytes = s.as_bytes();
+        if let Some(budget) = &mut self.output_budget {
+            bytes = &bytes[..bytes.len().min(*budget)];
+            *budget -= bytes.len();
+        }
+        self.io
+            .write(bytes)
+            .map_err(|e| StarfishError::OutputError(e, self.location()))?;
+        Ok(Step::Output(s))
+    }
+
+    /// now returns the time of day according to the CodeBox's Clock.
+    fn now(&self) -> NaiveTime {
+        match self.clock {
+            Clock::System => self.io.now(),
+            Clock::Virtual(time) => time,
+        }
+    }
+
+    /// location returns where the ><> currently is, for error reporting.
+    fn location(&self) -> Location {
+        Location {
+            x: self.f_x,
+            y: self.f_y,
+            direction: self.f_dir,
+        }
+    }
+
+    /// underflow builds a StackUnderflow error at the ><>'s current location.
+    fn underflow(&self) -> StarfishError {
+        StarfishError::StackUnderflow(self.location())
+    }
+
+    /// arithmetic_error converts an ArithmeticError into a StarfishError at the ><>'s current location.
+    fn arithmetic_error(&self, e: ArithmeticError) -> StarfishError {
+        match e {
+            ArithmeticError::DivisionByZero => StarfishError::DivisionByZero(self.location()),
+            ArithmeticError::Overflow => StarfishError::Overflow(self.location()),
+        }
+    }
+
+    /// coords converts x and y to coordinates. Any are allowed as long as they're whole numbers (fractions are
+    /// truncated) no bigger than 2^53. err builds the error used when they aren't.
+    fn coords(
+        &self,
+        x: &Value,
+        y: &Value,
+        err: fn(Location) -> StarfishError,
+    ) -> Result<(i64, i64), StarfishError> {
+        const LIMIT: f64 = (1u64 << 53) as f64;
+        let (x, y) = (x.to_f64().trunc(), y.to_f64().trunc());
+        if !(-LIMIT..=LIMIT).contains(&x) || !(-LIMIT..=LIMIT).contains(&y) {
+            return Err(err(self.location()));
+        }
+        Ok((x as i64, y as i64))
+    }
+
+    /// pop_coords pops y, then x, and converts them as CodeBox.Coords does.
+    fn pop_coords(
+        &mut self,
+        err: fn(Location) -> StarfishError,
+    ) -> Result<(i64, i64), StarfishError> {
+        let y = self.pop()?;
+        let x = self.pop()?;
+        self.coords(&x, &y, err)
+    }
+
+    /// jump_target converts x and y to coordinates to jump to, which must be inside the codebox's bounds.
+    fn jump_target(&self, x: &Value, y: &Value) -> Result<(i64, i64), StarfishError> {
+        let (x, y) = self.coords(x, y, StarfishError::OutOfBoundsJump)?;
+        if !self.grid.contains(x, y) {
+            return Err(StarfishError::OutOfBoundsJump(self.location()));
+        }
+        Ok((x, y))
+    }
+
+    /// pop_jump pops coordinates to jump to, as CodeBox.JumpTarget converts them.
+    fn pop_jump(&mut self) -> Result<(i64, i64), StarfishError> {
+        let y = self.pop()?;
+        let x = self.pop()?;
+        self.jump_target(&x, &y)
+    }
+
+    /// shift changes the fish's x/y coordinates based on CodeBox.f_dir, wrapping around the edges of the codebox.
+    pub fn shift(&mut self) {
+        (self.f_x, self.f_y) = self.grid.next(self.f_x, self.f_y, self.f_dir);
+    }
+
+    /// exe executes the instruction the ><> is currently on top of, returning what the caller should do about it.
+    /// Instructions other than movement are ignored while in deep-sea mode, including custom ones.
+    pub fn exe(&mut self, r: u8) -> Result<Step, StarfishError> {
+        if self.registry.contains(r) {
+            return self.exe_custom(r);
+        }
+        let instruction = match Instruction::from_byte(r) {
+            Some(instruction) if self.dialect.has(instruction) => instruction,
+            None if self.deep_sea => return Ok(Step::Continue),
+            _ => return Err(StarfishError::InvalidInstruction(r, self.location())),
+        };
+        if self.deep_sea && !instruction.info().deep_sea {
+            return Ok(Step::Continue);
+        }
+        let mut step = Step::Continue;
+
+        use Instruction::*;
+        match instruction {
+            Nop => {}
+            Right | Down | Left | Up | VerticalMirror | HorizontalMirror | TurnAround | Mirror
+            | BackMirror => {
+                let (dir, was_left) = mirror(r, self.f_dir);
+                self.f_dir = dir;
+                if let Some(was_left) = was_left {
+                    self.was_left = was_left;
                 }
-                return (None, false, 0.0);
             }
-            // *><> commands
-            b'O' => {
-                self.deep_sea = false;
-                return (None, false, 0.0);
+            Random => {
+                const DIRECTIONS: [Direction; 4] = [
+                    Direction::Right,
+                    Direction::Down,
+                    Direction::Left,
+                    Direction::Up,
+                ];
+                self.f_dir = DIRECTIONS[self.rng.gen_range(0..4)];
+                self.was_left = self.f_dir != Direction::Right;
             }
-            b'`' => {
+            Surface => self.deep_sea = false,
+            Hook => {
                 if self.f_dir == Direction::Down || self.f_dir == Direction::Up {
                     if self.was_left {
                         self.f_dir = Direction::Left;
                     } else {
                         self.f_dir = Direction::Right;
                     }
+                } else if self.escaped_hook {
+                    self.f_dir = Direction::Up;
+                    self.escaped_hook = false;
                 } else {
-                    if self.escaped_hook {
-                        self.f_dir = Direction::Up;
-                        self.escaped_hook = false;
-                    } else {
-                        self.f_dir = Direction::Down;
-                        self.escaped_hook = true;
-                    }
+                    self.f_dir = Direction::Down;
+                    self.escaped_hook = true;
                 }
-                return (None, false, 0.0);
             }
-            _ => {}
-        }
-
-        if self.deep_sea {
-            return (None, false, 0.0);
-        }
-
-        let mut output = None;
-
-        match r {
-            b';' => return (None, true, 0.0),
-            b'"' | b'\'' => {
+            Halt => step = Step::Halt,
+            DoubleQuote | SingleQuote => {
                 if self.string_mode == 0 {
                     self.string_mode = r;
                 } else if self.string_mode == r {
                     self.string_mode = 0;
                 }
             }
-            b'0' | b'1' | b'2' | b'3' | b'4' | b'5' | b'6' | b'7' | b'8' | b'9' => {
-                self.push((r - b'0') as f64)
+            Push0 | Push1 | Push2 | Push3 | Push4 | Push5 | Push6 | Push7 | Push8 | Push9
+            | PushA | PushB | PushC | PushD | PushE | PushF => {
+                self.push_int(instruction.digit().unwrap() as i64)
             }
-            b'a' | b'b' | b'c' | b'd' | b'e' | b'f' => self.push((r - b'a' + 10) as f64),
-            b'&' => self.register(),
-            b'o' => output = Some(char::from_u32(self.pop() as u32).unwrap().to_string()),
-            b'n' => output = Some((self.pop() as i64).to_string()),
-            b'r' => self.reverse_stack(),
-            b'+' => {
-                let a = self.pop();
-                let res = self.pop() + a;
-                self.push(res);
+            Register => self.register()?,
+            OutputChar => {
+                let v = self.pop()?;
+                let c = match self.encoding {
+                    Encoding::Byte if v.to_f64() >= 0.0 => char::from_u32(v.to_f64() as u32),
+                    Encoding::Byte => None,
+                    Encoding::Codepoint => to_char(&v),
+                };
+                match c {
+                    Some(c) => step = self.output(c.to_string())?,
+                    None => {
+                        return Err(StarfishError::InvalidCharacter(v.to_f64(), self.location()))
+                    }
+                }
             }
-            b'-' => {
-                let a = self.pop();
-                let res = self.pop() - a;
-                self.push(res);
+            OutputNumber => {
+                let v = self.pop()?;
+                step = self.output(v.to_string())?;
             }
-            b'*' => {
-                let a = self.pop();
-                let res = self.pop() * a;
-                self.push(res);
+            Reverse => self.reverse_stack(),
+            Add | Subtract | Multiply | Divide | Modulo => self.arithmetic(r)?,
+            Equal | Greater | Less => self.compare(r)?,
+            Skip => self.shift(),
+            SkipIfZero => {
+                if self.pop()?.is_zero() {
+                    self.shift();
+                }
             }
-            b',' => {
-                let a = self.pop();
-                let res = self.pop() / a;
-                self.push(res);
+            Jump => {
+                (self.f_x, self.f_y) = self.pop_jump()?;
             }
-            b'%' => {
-                let a = self.pop();
-                let res = self.pop().rem_euclid(a);
-                self.push(res);
+            Duplicate => self.extend_stack()?,
+            Drop => _ = self.pop()?,
+            Swap => self.stack_swap_two()?,
+            Rotate => self.stack_swap_three()?,
+            ShiftRight => self.stack_shift_right()?,
+            ShiftLeft => self.stack_shift_left()?,
+            CloseStack => self.close_stack()?,
+            NewStack => {
+                let size = self.pop()?.to_f64() as usize;
+                self.new_stack(size)?;
             }
-            b'=' => {
-                let a = self.pop();
-                if self.pop() == a {
-                    self.push(1.0);
-                } else {
-                    self.push(0.0);
-                }
+            Length => self.stack_length(),
+            Get => {
+                let (x, y) = self.pop_coords(StarfishError::OutOfBoundsAccess)?;
+                self.push(self.cell(x, y));
             }
-            b')' => {
-                let a = self.pop();
-                if self.pop() > a {
-                    self.push(1.0);
-                } else {
-                    self.push(0.0);
+            Put => {
+                let (x, y) = self.pop_coords(StarfishError::OutOfBoundsAccess)?;
+                let val = self.pop()?;
+                if let Some(observer) = &mut self.observer {
+                    let old = match self.grid.value(x, y) {
+                        Some(v) => v.clone(),
+                        None => self.numeric_mode.int(self.grid.get(x, y) as i64),
+                    };
+                    observer.on_codebox_write(x, y, &old, &val);
                 }
-            }
-            b'(' => {
-                let a = self.pop();
-                if self.pop() < a {
-                    self.push(1.0);
-                } else {
-                    self.push(0.0);
+                if self.grid.set(x, y, val) {
+                    self.generation += 1;
                 }
             }
-            b'!' => self.shift(),
-            b'?' => {
-                if self.pop() == 0.0 {
-                    self.shift();
+            Input => {
+                let input = match self.encoding {
+                    Encoding::Byte => self.read_byte().map(|b| b as i64),
+                    Encoding::Codepoint => self.read_char()?.map(|c| c as i64),
+                };
+                let input = self.numeric_mode.int(input.unwrap_or(-1));
+                if let Some(observer) = &mut self.observer {
+                    observer.on_input(&input);
                 }
+                self.push(input);
             }
-            b'.' => {
-                self.f_y = self.pop() as usize;
-                self.f_x = self.pop() as usize;
-            }
-            b':' => self.extend_stack(),
-            b'~' => _ = self.pop(),
-            b'$' => self.stack_swap_two(),
-            b'@' => self.stack_swap_three(),
-            b'}' => self.stack_shift_right(),
-            b'{' => self.stack_shift_left(),
-            b']' => self.close_stack(),
-            b'[' => {
-                let size = self.pop() as usize;
-                self.new_stack(size);
-            }
-            b'l' => self.stack_length(),
-            b'g' => {
-                let y = self.pop() as usize;
-                let x = self.pop() as usize;
-                self.push(self.code_box[y][x] as f64);
-            }
-            b'p' => {
-                let y = self.pop() as usize;
-                let x = self.pop() as usize;
-                let val = self.pop() as u8;
-                self.code_box[y][x] = val;
-            }
-            b'i' => {
-                let mut r = -1.0;
-                #[cfg(not(target_arch = "wasm32"))]
-                {
-                    match &self.file {
-                        None => match self.stdin_out.try_recv() {
-                            Ok(v) => r = v as f64,
-                            Err(_e) => {}
-                        },
-                        Some(_inner) => {
-                            let mut bs = [0];
-                            let read_res = self.file.as_ref().unwrap().read(&mut bs);
-                            match read_res {
-                                Ok(v) => {
-                                    if v > 0 {
-                                        r = bs[0] as f64;
-                                    }
-                                }
-                                Err(_e) => {}
+            Hour => self.push_int(self.now().hour() as i64),
+            Minute => self.push_int(self.now().minute() as i64),
+            Second => self.push_int(self.now().second() as i64),
+            Sleep => {
+                let sleep_ms = self.pop()?.to_f64() * 100.0;
+                self.io
+                    .flush()
+                    .map_err(|e| StarfishError::OutputError(e, self.location()))?;
+                let duration = Duration::try_from_secs_f64(sleep_ms.max(0.0) / 1000.0)
+                    .unwrap_or(Duration::MAX);
+                match &mut self.clock {
+                    Clock::System => {
+                        let duration = match self.deadline {
+                            Some(deadline) => {
+                                duration.min(deadline.saturating_duration_since(Instant::now()))
                             }
+                            None => duration,
+                        };
+                        if !duration.is_zero() {
+                            self.io.sleep(duration);
                         }
+                        step = Step::Sleep(duration.as_secs_f64() * 1000.0);
+                    }
+                    Clock::Virtual(time) => {
+                        *time += chrono::Duration::from_std(duration).unwrap_or_default();
                     }
                 }
-                #[cfg(target_arch = "wasm32")]
-                {
-                    match self.stdin_out.try_recv() {
-                        Ok(v) => r = v as f64,
-                        Err(_e) => {}
+            }
+            DeepSea => self.deep_sea = true,
+            File => {
+                let count = self.pop()?.to_f64() as usize;
+                let vals = self.pop_text(count)?;
+                match self.file.take() {
+                    Some(_) => self
+                        .fs
+                        .write(&self.file_path, &vals)
+                        .map_err(|e| StarfishError::FileError(e, self.location()))?,
+                    None => {
+                        self.file_path = String::from_utf8(vals).map_err(|e| {
+                            StarfishError::FileError(
+                                std::io::Error::new(std::io::ErrorKind::InvalidData, e),
+                                self.location(),
+                            )
+                        })?;
+                        let file = self
+                            .fs
+                            .open(&self.file_path)
+                            .map_err(|e| StarfishError::FileError(e, self.location()))?;
+                        self.file = Some(file);
                     }
                 }
-                self.push(r);
             }
-            // *><> commands
-            b'h' => self.push(Local::now().hour() as f64),
-            b'm' => self.push(Local::now().minute() as f64),
-            b's' => self.push(Local::now().second() as f64),
-            b'S' => {
-                _ = stdout().flush();
-                return (output, false, self.pop() * 100.0);
+            Call => self.call()?,
+            Return => self.ret()?,
+            StackUp => {
+                if self.p + 1 >= self.stacks.len() {
+                    return Err(StarfishError::EmptyRegisterStack(self.location()));
+                }
+                self.p += 1;
             }
-            b'u' => self.deep_sea = true,
-            b'F' => {
-                #[cfg(target_arch = "wasm32")]
-                return (Some(String::from("\nsomething smells fishy...")), true, 0.0);
-                #[cfg(not(target_arch = "wasm32"))]
-                {
-                    let count = self.pop() as usize;
-                    let vals = self.stacks[self.p].get_bytes(count);
-                    match &self.file {
-                        Some(_inner) => {
-                            self.file = None;
-                            let mut file = File::create(&self.file_path).unwrap();
-                            _ = file.write_all(&vals);
-                        }
-                        None => {
-                            self.file_path = str::from_utf8(&vals).unwrap().to_string();
-                            let file_res = File::open(&self.file_path);
-                            match file_res {
-                                Ok(v) => {
-                                    self.file = Some(v);
-                                }
-                                Err(_e) => {
-                                    self.file = Some(File::create(&self.file_path).unwrap());
-                                    self.file = Some(File::open(&self.file_path).unwrap());
-                                }
-                            }
-                        }
-                    }
+            StackDown => {
+                if self.p == 0 {
+                    return Err(StarfishError::EmptyRegisterStack(self.location()));
                 }
+                self.p -= 1;
             }
-            b'C' => self.call(),
-            b'R' => self.ret(),
-            b'I' => self.p += 1,
-            b'D' => self.p -= 1,
-            _ => return (Some(String::from("\nsomething smells fishy...")), true, 0.0),
         }
 
-        return (output, false, 0.0);
+        Ok(step)
     }
 
-    /// swim causes the ><> to execute an instruction, then move. It returns a string of non-zero length when it has output and true when it encounters ";".
-    pub fn swim(&mut self) -> (Option<String>, bool, f64) {
-        let y = self.f_y;
-        let x = self.f_x;
-        let r = self.code_box[y][x];
-        let string_mode = self.string_mode != 0;
+    /// exe_custom executes the custom instruction r.
+    fn exe_custom(&mut self, r: u8) -> Result<Step, StarfishError> {
+        if self.deep_sea {
+            return Ok(Step::Continue);
+        }
+        let f = self.registry.get(r).expect("r is a custom instruction");
+        let action = f(&mut self.stacks[self.p])
+            .map_err(|e| StarfishError::InstructionFailed(r, e, self.location()))?;
+        match action {
+            Action::Continue => Ok(Step::Continue),
+            Action::Output(s) => self.output(s),
+            Action::Halt => Ok(Step::Halt),
+            Action::Jump(x, y) => {
+                if !self.grid.contains(x, y) {
+                    return Err(StarfishError::OutOfBoundsJump(self.location()));
+                }
+                (self.f_x, self.f_y) = (x, y);
+                Ok(Step::Continue)
+            }
+        }
+    }
 
-        let mut output = None;
-        let mut end = false;
-        let mut sleep_ms: f64 = 0.0;
+    /// arithmetic implements "+", "-", "*", "," and "%".
+    fn arithmetic(&mut self, r: u8) -> Result<(), StarfishError> {
+        // Floats are by far the most common, and can be worked on in place. Observers need to see the pops and
+        // push, so they go the long way round.
+        let s = &mut self.stacks[self.p].s;
+        if let (None, [.., Value::Float(b), Value::Float(a)]) = (&self.observer, s.as_mut_slice()) {
+            let res = match r {
+                b'+' => *b + *a,
+                b'-' => *b - *a,
+                b'*' => *b * *a,
+                b',' if *a != 0.0 => *b / *a,
+                b'%' if *a != 0.0 => b.rem_euclid(*a),
+                _ => return Err(StarfishError::DivisionByZero(self.location())),
+            };
+            *b = res;
+            s.pop();
+            return Ok(());
+        }
+        let a = self.pop()?;
+        let b = self.pop()?;
+        let res = match r {
+            b'+' => b.checked_add(a),
+            b'-' => b.checked_sub(a),
+            b'*' => b.checked_mul(a),
+            b',' => b.checked_div(a),
+            _ => b.checked_rem(a),
+        }
+        .map_err(|e| self.arithmetic_error(e))?;
+        self.push(res);
+        Ok(())
+    }
 
-        if string_mode && r != self.string_mode {
-            self.push(r as f64);
-        } else {
-            (output, end, sleep_ms) = self.exe(r);
+    /// compare implements "=", ")" and "(".
+    fn compare(&mut self, r: u8) -> Result<(), StarfishError> {
+        let s = &mut self.stacks[self.p].s;
+        if let (None, [.., Value::Float(b), Value::Float(a)]) = (&self.observer, s.as_mut_slice()) {
+            let res = match r {
+                b'=' => *b == *a,
+                b')' => *b > *a,
+                _ => *b < *a,
+            };
+            *b = res as i64 as f64;
+            s.pop();
+            return Ok(());
         }
+        let a = self.pop()?;
+        let b = self.pop()?;
+        let res = match r {
+            b'=' => b == a,
+            b')' => b > a,
+            _ => b < a,
+        };
+        self.push_int(res as i64);
+        Ok(())
+    }
+
+    /// swim causes the ><> to execute an instruction, then move. If the instruction fails the ><> stays where it
+    /// is and the error is returned.
+    pub fn swim(&mut self) -> Result<Step, StarfishError> {
+        if self.observer.is_none() {
+            return self.swim_unobserved();
+        }
+        self.observe(|observer, codebox| observer.on_step(codebox));
+        let result = self.swim_unobserved();
+        self.observe(|observer, codebox| observer.on_instruction(codebox, &result));
+        if let Ok(Step::Halt) = result {
+            self.observe(|observer, codebox| observer.on_halt(codebox));
+        }
+        result
+    }
+
+    /// swim_unobserved implements CodeBox.Swim, apart from calling the Observer.
+    fn swim_unobserved(&mut self) -> Result<Step, StarfishError> {
+        let r = self.grid.get(self.f_x, self.f_y);
+
+        let step = if let Some(v) = self.grid.value(self.f_x, self.f_y) {
+            if self.string_mode == 0 {
+                return Err(StarfishError::InvalidCell(v.clone(), self.location()));
+            }
+            self.push(v.clone());
+            Step::Continue
+        } else if self.string_mode != 0 && r != self.string_mode {
+            self.push_int(r as i64);
+            Step::Continue
+        } else {
+            self.exe(r)?
+        };
         self.shift();
-        return (output, end, sleep_ms);
+        Ok(step)
     }
 
-    /// push appends r to the end of the current stack.
-    pub fn push(&mut self, r: f64) {
+    /// push appends r to the end of the current stack. r should be in the CodeBox's NumericMode.
+    pub fn push(&mut self, r: Value) {
+        if let Some(observer) = &mut self.observer {
+            observer.on_stack_push(self.p, &r);
+        }
         self.stacks[self.p].push(r);
     }
 
+    /// push_int appends i to the end of the current stack.
+    pub fn push_int(&mut self, i: i64) {
+        self.push(self.numeric_mode.int(i));
+    }
+
     /// pop removes the value on the end of the current stack and returns it.
-    pub fn pop(&mut self) -> f64 {
-        self.stacks[self.p].pop()
+    pub fn pop(&mut self) -> Result<Value, StarfishError> {
+        let v = self.stacks[self.p].pop().ok_or_else(|| self.underflow())?;
+        if let Some(observer) = &mut self.observer {
+            observer.on_stack_pop(self.p, &v);
+        }
+        Ok(v)
     }
 
     /// stack_length implements "l" on the current stack.
     pub fn stack_length(&mut self) {
-        self.push(self.stacks[self.p].s.len() as f64);
+        self.push_int(self.stacks[self.p].s.len() as i64);
     }
 
     /// register implements "&" on the current stack.
-    pub fn register(&mut self) {
-        self.stacks[self.p].register();
+    pub fn register(&mut self) -> Result<(), StarfishError> {
+        let pushing = self.stacks[self.p].register_value().is_some();
+        self.stacks[self.p]
+            .register()
+            .ok_or_else(|| self.underflow())?;
+        if let Some(observer) = &mut self.observer {
+            let stack = &self.stacks[self.p];
+            match (pushing, stack.s.last(), stack.register_value()) {
+                (true, Some(v), _) => observer.on_stack_push(self.p, v),
+                (false, _, Some(v)) => observer.on_stack_pop(self.p, v),
+                _ => {}
+            }
+        }
+        Ok(())
     }
 
     /// reverse_stack implements "r" on the current stack.
@@ -673,90 +1096,144 @@ impl CodeBox {
     }
 
     /// extend_stack implements ":" on the current stack.
-    pub fn extend_stack(&mut self) {
-        self.stacks[self.p].extend();
+    pub fn extend_stack(&mut self) -> Result<(), StarfishError> {
+        self.stacks[self.p]
+            .extend()
+            .ok_or_else(|| self.underflow())?;
+        if let (Some(observer), Some(v)) = (&mut self.observer, self.stacks[self.p].s.last()) {
+            observer.on_stack_push(self.p, v);
+        }
+        Ok(())
     }
 
     /// stack_swap_two implements "$" on the current stack.
-    pub fn stack_swap_two(&mut self) {
-        self.stacks[self.p].swap_two();
+    pub fn stack_swap_two(&mut self) -> Result<(), StarfishError> {
+        self.stacks[self.p]
+            .swap_two()
+            .ok_or_else(|| self.underflow())
     }
 
     /// stack_swap_three implements "@" on the current stack.
-    pub fn stack_swap_three(&mut self) {
-        self.stacks[self.p].swap_three();
+    pub fn stack_swap_three(&mut self) -> Result<(), StarfishError> {
+        self.stacks[self.p]
+            .swap_three()
+            .ok_or_else(|| self.underflow())
     }
 
     /// stack_shift_right implements "}" on the current stack.
-    pub fn stack_shift_right(&mut self) {
-        self.stacks[self.p].shift_right();
+    pub fn stack_shift_right(&mut self) -> Result<(), StarfishError> {
+        self.stacks[self.p]
+            .shift_right()
+            .ok_or_else(|| self.underflow())
     }
 
     /// stack_shift_left implements "{" on the current stack.
-    pub fn stack_shift_left(&mut self) {
-        self.stacks[self.p].shift_left();
+    pub fn stack_shift_left(&mut self) -> Result<(), StarfishError> {
+        self.stacks[self.p]
+            .shift_left()
+            .ok_or_else(|| self.underflow())
     }
 
     /// close_stack implements "]".
-    pub fn close_stack(&mut self) {
-        if self.compatibility_mode {
+    pub fn close_stack(&mut self) -> Result<(), StarfishError> {
+        if self.p == 0 {
+            return Err(StarfishError::EmptyRegisterStack(self.location()));
+        }
+        if self.dialect == Dialect::FishInterpreterCom {
             self.stacks[self.p].reverse(); // This is done to match the old fishlanguage.com interpreter.
         }
-        let mut old_stack = self.stacks[self.p].s.to_vec();
-        self.stacks.remove(self.p);
+        if let Some(observer) = &mut self.observer {
+            observer.on_stack_close(self.p);
+        }
+        let mut old_stack = self.stacks.remove(self.p).s;
         self.p -= 1;
         self.stacks[self.p].s.append(&mut old_stack);
+        Ok(())
     }
 
     /// new_stack implements "[".
-    pub fn new_stack(&mut self, n: usize) {
+    pub fn new_stack(&mut self, n: usize) -> Result<(), StarfishError> {
         let len = self.stacks[self.p].s.len();
-        let vals = self.stacks[self.p]
-            .s
-            .drain(len - n..len)
-            .as_slice()
-            .to_vec();
+        if n > len {
+            return Err(self.underflow());
+        }
+        let vals = self.stacks[self.p].s.split_off(len - n);
         self.p += 1;
         self.stacks.insert(self.p, Stack::new(Some(vals)));
-        if self.compatibility_mode {
+        if self.dialect == Dialect::FishInterpreterCom {
             self.stacks[self.p].reverse(); // This is done to match the old fishlanguage.com interpreter.
         }
+        if let Some(observer) = &mut self.observer {
+            observer.on_stack_create(self.p);
+        }
+        Ok(())
     }
 
     /// call implements "C".
-    pub fn call(&mut self) {
+    pub fn call(&mut self) -> Result<(), StarfishError> {
+        let (x, y) = self.pop_jump()?;
         self.stacks.insert(
             self.p,
-            Stack::new(Some(vec![self.f_x as f64, self.f_y as f64])),
+            Stack::new(Some(vec![
+                self.numeric_mode.int(self.f_x),
+                self.numeric_mode.int(self.f_y),
+            ])),
         );
+        if let Some(observer) = &mut self.observer {
+            observer.on_stack_create(self.p);
+        }
         self.p += 1;
-        self.f_y = self.pop() as usize;
-        self.f_x = self.pop() as usize;
+        self.f_x = x;
+        self.f_y = y;
+        Ok(())
     }
 
     /// ret implements "R".
-    pub fn ret(&mut self) {
+    pub fn ret(&mut self) -> Result<(), StarfishError> {
+        if self.p == 0 {
+            return Err(StarfishError::EmptyRegisterStack(self.location()));
+        }
+        // The return address is checked before anything changes, so an "R" which fails leaves the stacks alone.
+        let (x, y) = match self.stacks[self.p - 1].s.as_slice() {
+            [.., x, y] => self.jump_target(x, y)?,
+            _ => return Err(self.underflow()),
+        };
         self.p -= 1;
-        self.f_y = self.pop() as usize;
-        self.f_x = self.pop() as usize;
+        for _ in 0..2 {
+            self.pop()?;
+        }
+        (self.f_x, self.f_y) = (x, y);
+        if let Some(observer) = &mut self.observer {
+            observer.on_stack_close(self.p);
+        }
         self.stacks.remove(self.p);
+        Ok(())
     }
 
     /// print outputs the codebox to stdout.
     pub fn print(&self, clear: bool) {
+        _ = self.write_codebox(&mut std::io::stdout().lock(), clear);
+    }
+
+    /// write_codebox writes the cells in CodeBox.View to out as CodeBox.Print does, with the ><> between asterisks.
+    /// If clear is set it starts by moving the cursor to the top left of the terminal.
+    pub fn write_codebox(&self, out: &mut impl Write, clear: bool) -> std::io::Result<()> {
         if clear {
-            print!("\x1b[0;H");
+            write!(out, "\x1b[0;H")?;
         }
-        for y in 0..self.height {
-            for x in 0..self.width {
+        let ((x0, y0), (width, height)) = self.view();
+        for y in y0..y0 + height as i64 {
+            for x in x0..x0 + width as i64 {
+                let r = self.cell_char(x, y);
                 if x == self.f_x && y == self.f_y {
-                    print!("*{}*", self.code_box[y][x] as char);
+                    write!(out, "*{}*", r)?;
                 } else {
-                    print!(" {} ", self.code_box[y][x] as char);
+                    write!(out, " {} ", r)?;
                 }
             }
-            println!();
+            writeln!(out)?;
         }
+        Ok(())
     }
 
     /// string_stack returns a copy of the current stack as a string.
@@ -764,23 +1241,123 @@ impl CodeBox {
         self.stacks[self.p].to_string()
     }
 
-    /// size returns the width and height of the codebox.
-    pub fn size(&self) -> (usize, usize) {
-        return (self.width, self.height);
+    /// size returns the width and height of the codebox's bounds, the smallest rectangle containing the script and
+    /// every cell written to by "p".
+    pub fn size(&self) -> (u64, u64) {
+        self.grid.size()
+    }
+
+    /// origin returns the x/y coordinates of the top left corner of the codebox's bounds. It's (0, 0) unless "p" has
+    /// written to a negative coordinate.
+    pub fn origin(&self) -> (i64, i64) {
+        self.grid.origin()
+    }
+
+    /// view returns the top left corner and the size of the part of the codebox's bounds CodeBox.WriteCodeBox and
+    /// CodeBox.ViewBox output. That's all of it, unless "p" has stretched the bounds to more than VIEW_SIZE cells
+    /// across or down, in which case it's VIEW_SIZE cells that way, as near to centred on the ><> as fits.
+    pub fn view(&self) -> ((i64, i64), (u64, u64)) {
+        let (x0, y0) = self.grid.origin();
+        let (width, height) = self.grid.size();
+        let fit = |start: i64, len: u64, at: i64| {
+            if len <= VIEW_SIZE {
+                (start, len)
+            } else {
+                let last = start.saturating_add_unsigned(len - VIEW_SIZE);
+                let centred = at.saturating_sub(VIEW_SIZE as i64 / 2);
+                (centred.clamp(start, last), VIEW_SIZE)
+            }
+        };
+        let (x, width) = fit(x0, width, self.f_x);
+        let (y, height) = fit(y0, height, self.f_y);
+        ((x, y), (width, height))
+    }
+
+    /// code_box outputs a copy of every cell within the codebox's bounds, row by row starting at CodeBox.Origin. Once
+    /// "p" has written far away that's a lot of cells; CodeBox.ViewBox copies only those around the ><>.
+    pub fn code_box(&self) -> Vec<Vec<Value>> {
+        let (x0, y0) = self.grid.origin();
+        let (width, height) = self.grid.size();
+        self.cells(x0, y0, width, height)
+    }
+
+    /// view_box outputs a copy of every cell within CodeBox.View, row by row starting at its top left corner.
+    pub fn view_box(&self) -> Vec<Vec<Value>> {
+        let ((x0, y0), (width, height)) = self.view();
+        self.cells(x0, y0, width, height)
+    }
+
+    /// cells copies the width by height rectangle of cells whose top left corner is x0/y0, row by row.
+    fn cells(&self, x0: i64, y0: i64, width: u64, height: u64) -> Vec<Vec<Value>> {
+        (y0..y0 + height as i64)
+            .map(|y| (x0..x0 + width as i64).map(|x| self.cell(x, y)).collect())
+            .collect()
+    }
+
+    /// dialect returns the flavour of the language the CodeBox understands.
+    pub fn dialect(&self) -> Dialect {
+        self.dialect
+    }
+
+    /// numeric_mode returns how values on the stacks are represented.
+    pub fn numeric_mode(&self) -> NumericMode {
+        self.numeric_mode
     }
 
-    /// code_box outputs a copy of the current state of the codebox.
-    pub fn code_box(&self) -> Vec<Vec<u8>> {
-        return self.code_box.to_vec();
+    /// encoding returns how the script, input and output are mapped to values.
+    pub fn encoding(&self) -> Encoding {
+        self.encoding
     }
 
     /// deep_sea returns if the ><> is in deepsea mode or not.
     pub fn deep_sea(&self) -> bool {
-        return self.deep_sea;
+        self.deep_sea
     }
 
     /// position returns the x/y coordinates of the ><>.
-    pub fn position(&self) -> (usize, usize) {
-        return (self.f_x, self.f_y);
+    pub fn position(&self) -> (i64, i64) {
+        (self.f_x, self.f_y)
+    }
+
+    /// direction returns the way the ><> is swimming.
+    pub fn direction(&self) -> Direction {
+        self.f_dir
+    }
+
+    /// string_mode returns the quote which opened the current string, or None if the ><> isn't in string mode.
+    pub fn string_mode(&self) -> Option<u8> {
+        if self.string_mode == 0 {
+            None
+        } else {
+            Some(self.string_mode)
+        }
+    }
+
+    /// cell returns the value in the cell at x/y. Cells which have never been set are 0.
+    pub fn cell(&self, x: i64, y: i64) -> Value {
+        match self.grid.value(x, y) {
+            Some(v) => v.clone(),
+            None => self.numeric_mode.int(self.grid.get(x, y) as i64),
+        }
+    }
+
+    /// cell_char returns how the cell at x/y is drawn: the character whose code point it holds, a space if it's
+    /// empty, or U+FFFD if it holds a value which isn't a character.
+    pub fn cell_char(&self, x: i64, y: i64) -> char {
+        match (self.grid.get(x, y), self.grid.value(x, y)) {
+            (_, Some(v)) => to_char(v).unwrap_or(char::REPLACEMENT_CHARACTER),
+            (0, None) => ' ',
+            (r, None) => r as char,
+        }
+    }
+
+    /// stacks returns every stack, bottom first. CodeBox.CurrentStack is the index of the one in use.
+    pub fn stacks(&self) -> &[Stack] {
+        &self.stacks
+    }
+
+    /// current_stack returns the index into CodeBox.Stacks of the stack instructions operate on.
+    pub fn current_stack(&self) -> usize {
+        self.p
     }
 }
@@ -0,0 +1,408 @@
+use std::collections::HashSet;
+use std::fmt;
+
+use serde::Serialize;
+
+use crate::{mirror, CodeBox, Direction, Instruction};
+
+/// Severity is how much a Diagnostic matters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    /// The ><> fails if it gets here.
+    Error,
+    /// Probably a mistake.
+    Warning,
+    /// Worth knowing, but not wrong.
+    Info,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+            Severity::Info => "info",
+        })
+    }
+}
+
+/// LintKind is what a Diagnostic is about.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum LintKind {
+    /// Cells holding something which the ><> can never reach.
+    Unreachable,
+    /// No ";" can be reached, so the ><> can't halt without an error.
+    NoHalt,
+    /// A reachable cell which isn't an instruction.
+    InvalidInstruction,
+    /// A reachable *><> instruction, which the dialect doesn't have.
+    Dialect,
+    /// A string which isn't closed before wrapping back around to its opening quote.
+    UnclosedString,
+    /// "x", "h", "m", "s" or "i", whose results depend on more than the script.
+    Nondeterministic,
+    /// "F", which uses the file system.
+    FileSystem,
+    /// "p", which changes the codebox as the ><> swims, so the lint may not match what runs.
+    SelfModifying,
+    /// ".", "C" or "R" to coordinates worked out while running, so what's reachable isn't known.
+    ComputedJump,
+    /// ".", "C" or "R" to constant coordinates outside the codebox.
+    JumpOutOfBounds,
+}
+
+/// Diagnostic is a problem found by CodeBox.Lint.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Diagnostic {
+    pub kind: LintKind,
+    pub severity: Severity,
+    pub x: i64,
+    pub y: i64,
+    /// length is how many cells along the row the diagnostic covers, starting at x/y.
+    pub length: u64,
+    pub message: String,
+}
+
+/// State is everything the reachability walk knows about the ><> at a cell. consts are the top two values on the
+/// stack, if they were pushed by literals, so jumps to constant coordinates can be followed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct State {
+    x: i64,
+    y: i64,
+    dir: Direction,
+    deep_sea: bool,
+    consts: [Option<i64>; 2],
+}
+
+/// Walk finds every cell the ><> can reach, recording diagnostics as it goes.
+pub(crate) struct Walk<'a> {
+    codebox: &'a CodeBox,
+    seen: HashSet<State>,
+    queue: Vec<State>,
+    // Cells the ><> executes, or swims through as part of a string.
+    reached: HashSet<(i64, i64)>,
+    // Cells the ><> executes, and the opening quotes of strings with the direction they're read in.
+    pub(crate) executed: HashSet<(i64, i64)>,
+    pub(crate) strings: HashSet<(i64, i64, Direction)>,
+    diagnostics: Vec<Diagnostic>,
+    reported: HashSet<(i64, i64, LintKind)>,
+    // The cells of reachable "C"s, and the directions and modes reachable "R"s are hit in.
+    calls: Vec<(i64, i64)>,
+    returns: Vec<(Direction, bool)>,
+    halts: bool,
+    pub(crate) computed: bool,
+}
+
+impl Walk<'_> {
+    fn report(&mut self, kind: LintKind, severity: Severity, x: i64, y: i64, message: String) {
+        if self.reported.insert((x, y, kind)) {
+            self.diagnostics.push(Diagnostic {
+                kind,
+                severity,
+                x,
+                y,
+                length: 1,
+                message,
+            });
+        }
+    }
+
+    /// visit queues the ><> arriving at x/y, if it hasn't been there in the same state before.
+    fn visit(&mut self, x: i64, y: i64, dir: Direction, deep_sea: bool, consts: [Option<i64>; 2]) {
+        let state = State {
+            x,
+            y,
+            dir,
+            deep_sea,
+            consts,
+        };
+        if self.seen.insert(state) {
+            self.queue.push(state);
+        }
+    }
+
+    /// next queues the ><> swimming on from x/y, skipping skip cells first.
+    fn next(
+        &mut self,
+        s: State,
+        x: i64,
+        y: i64,
+        dir: Direction,
+        skip: usize,
+        consts: [Option<i64>; 2],
+    ) {
+        let grid = &self.codebox.grid;
+        let (mut x, mut y) = grid.next(x, y, dir);
+        for _ in 0..skip {
+            (x, y) = grid.next(x, y, dir);
+        }
+        self.visit(x, y, dir, s.deep_sea, consts);
+    }
+
+    /// jump queues the ><> jumping to the constant coordinates on top of the stack, returning false if they aren't
+    /// known.
+    fn jump(&mut self, s: State) -> bool {
+        let [Some(x), Some(y)] = s.consts else {
+            self.computed = true;
+            self.report(
+                LintKind::ComputedJump,
+                Severity::Info,
+                s.x,
+                s.y,
+                String::from("jumps to coordinates worked out while running, so unreachable code isn't reported"),
+            );
+            return false;
+        };
+        if !self.codebox.grid.contains(x, y) {
+            self.report(
+                LintKind::JumpOutOfBounds,
+                Severity::Error,
+                s.x,
+                s.y,
+                format!("jumps to ({}, {}), which is outside of the codebox", x, y),
+            );
+            return false;
+        }
+        self.next(s, x, y, s.dir, 0, [None; 2]);
+        true
+    }
+
+    fn step(&mut self, s: State) {
+        let codebox = self.codebox;
+        let (x, y) = (s.x, s.y);
+        self.reached.insert((x, y));
+        self.executed.insert((x, y));
+        if let Some(v) = codebox.grid.value(x, y) {
+            let message = format!("holds {}, which isn't an instruction", v);
+            self.report(LintKind::InvalidInstruction, Severity::Error, x, y, message);
+            return;
+        }
+        let r = codebox.grid.get(x, y);
+        if codebox.registry.contains(r) {
+            // A custom instruction could do anything, but most carry on swimming.
+            self.next(s, x, y, s.dir, 0, [None; 2]);
+            return;
+        }
+        if !codebox.dialect.allows(r) {
+            let message = format!(
+                "{:?} is a *><> instruction, which the {} dialect doesn't have",
+                r as char, codebox.dialect
+            );
+            self.report(LintKind::Dialect, Severity::Error, x, y, message);
+            return;
+        }
+        let Some(instruction) = Instruction::from_byte(r) else {
+            if s.deep_sea {
+                self.next(s, x, y, s.dir, 0, s.consts);
+            } else {
+                let message = format!("{:?} isn't an instruction", r as char);
+                self.report(LintKind::InvalidInstruction, Severity::Error, x, y, message);
+            }
+            return;
+        };
+        if s.deep_sea && !instruction.info().deep_sea {
+            self.next(s, x, y, s.dir, 0, s.consts);
+            return;
+        }
+        let nondeterministic = |walk: &mut Walk, what: &str| {
+            let message = format!("{:?} {}, so runs can differ", r as char, what);
+            walk.report(LintKind::Nondeterministic, Severity::Info, x, y, message);
+        };
+
+        use Instruction::*;
+        match instruction {
+            Right | Down | Left | Up | VerticalMirror | HorizontalMirror | TurnAround | Mirror
+            | BackMirror => {
+                let (dir, _) = mirror(r, s.dir);
+                self.next(s, x, y, dir, 0, s.consts);
+            }
+            Random => {
+                nondeterministic(self, "picks a random direction");
+                for dir in [
+                    Direction::Right,
+                    Direction::Down,
+                    Direction::Left,
+                    Direction::Up,
+                ] {
+                    self.next(s, x, y, dir, 0, s.consts);
+                }
+            }
+            Hook => {
+                let dirs = match s.dir {
+                    Direction::Up | Direction::Down => [Direction::Left, Direction::Right],
+                    Direction::Left | Direction::Right => [Direction::Up, Direction::Down],
+                };
+                for dir in dirs {
+                    self.next(s, x, y, dir, 0, s.consts);
+                }
+            }
+            Surface => self.visit_next(s, false, s.consts),
+            Nop => self.next(s, x, y, s.dir, 0, s.consts),
+            Halt => self.halts = true,
+            DoubleQuote | SingleQuote => {
+                self.strings.insert((x, y, s.dir));
+                let grid = &codebox.grid;
+                let mut consts = s.consts;
+                let (mut cx, mut cy) = grid.next(x, y, s.dir);
+                while grid.get(cx, cy) != r || grid.value(cx, cy).is_some() {
+                    self.reached.insert((cx, cy));
+                    let v = grid
+                        .value(cx, cy)
+                        .map_or(grid.get(cx, cy) as i64, |v| v.to_f64() as i64);
+                    consts = [consts[1], Some(v)];
+                    (cx, cy) = grid.next(cx, cy, s.dir);
+                }
+                self.reached.insert((cx, cy));
+                if (cx, cy) == (x, y) {
+                    let message = format!(
+                        "the string opened by {:?} wraps around to it without being closed",
+                        r as char
+                    );
+                    self.report(LintKind::UnclosedString, Severity::Warning, x, y, message);
+                }
+                self.next(s, cx, cy, s.dir, 0, consts);
+            }
+            Push0 | Push1 | Push2 | Push3 | Push4 | Push5 | Push6 | Push7 | Push8 | Push9
+            | PushA | PushB | PushC | PushD | PushE | PushF => {
+                let v = instruction.digit().unwrap() as i64;
+                self.next(s, x, y, s.dir, 0, [s.consts[1], Some(v)]);
+            }
+            Skip => self.next(s, x, y, s.dir, 1, s.consts),
+            SkipIfZero => {
+                self.next(s, x, y, s.dir, 0, [None; 2]);
+                self.next(s, x, y, s.dir, 1, [None; 2]);
+            }
+            Jump => _ = self.jump(s),
+            Call => {
+                self.calls.push((x, y));
+                for (dir, deep_sea) in self.returns.clone() {
+                    self.visit_return(x, y, dir, deep_sea);
+                }
+                self.jump(s);
+            }
+            Return => {
+                if !self.returns.contains(&(s.dir, s.deep_sea)) {
+                    self.returns.push((s.dir, s.deep_sea));
+                    for (cx, cy) in self.calls.clone() {
+                        self.visit_return(cx, cy, s.dir, s.deep_sea);
+                    }
+                }
+            }
+            DeepSea => self.visit_next(s, true, s.consts),
+            Put => {
+                let message = String::from(
+                    "changes the codebox while running, so this lint may not match what runs",
+                );
+                self.report(LintKind::SelfModifying, Severity::Info, x, y, message);
+                self.next(s, x, y, s.dir, 0, [None; 2]);
+            }
+            File => {
+                let message = String::from("reads or writes files");
+                self.report(LintKind::FileSystem, Severity::Info, x, y, message);
+                self.next(s, x, y, s.dir, 0, [None; 2]);
+            }
+            Hour | Minute | Second => {
+                nondeterministic(self, "reads the clock");
+                self.next(s, x, y, s.dir, 0, [None; 2]);
+            }
+            Input => {
+                nondeterministic(self, "reads input");
+                self.next(s, x, y, s.dir, 0, [None; 2]);
+            }
+            _ => self.next(s, x, y, s.dir, 0, [None; 2]),
+        }
+    }
+
+    /// visit_next queues the ><> swimming on from s in deep-sea mode or not.
+    fn visit_next(&mut self, s: State, deep_sea: bool, consts: [Option<i64>; 2]) {
+        let (x, y) = self.codebox.grid.next(s.x, s.y, s.dir);
+        self.visit(x, y, s.dir, deep_sea, consts);
+    }
+
+    /// visit_return queues the ><> returning with "R" to the "C" at x/y.
+    fn visit_return(&mut self, x: i64, y: i64, dir: Direction, deep_sea: bool) {
+        let (x, y) = self.codebox.grid.next(x, y, dir);
+        self.visit(x, y, dir, deep_sea, [None; 2]);
+    }
+
+    /// unreachable reports every run of cells along a row which hold something but were never reached.
+    fn unreachable(&mut self) {
+        let grid = &self.codebox.grid;
+        let (x0, y0) = grid.origin();
+        let (width, height) = grid.size();
+        for y in y0..y0 + height as i64 {
+            let mut start = None;
+            for x in x0..=x0 + width as i64 {
+                let filled = x < x0 + width as i64
+                    && !self.reached.contains(&(x, y))
+                    && (grid.value(x, y).is_some() || !matches!(grid.get(x, y), 0 | b' '));
+                match (filled, start) {
+                    (true, None) => start = Some(x),
+                    (false, Some(sx)) => {
+                        start = None;
+                        self.diagnostics.push(Diagnostic {
+                            kind: LintKind::Unreachable,
+                            severity: Severity::Warning,
+                            x: sx,
+                            y,
+                            length: (x - sx) as u64,
+                            message: String::from("unreachable code"),
+                        });
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+}
+
+impl CodeBox {
+    /// walk walks every path the ><> could take from where it is, for CodeBox.Lint and CodeBox.Explain.
+    pub(crate) fn walk(&self) -> Walk<'_> {
+        let mut walk = Walk {
+            codebox: self,
+            seen: HashSet::new(),
+            queue: Vec::new(),
+            reached: HashSet::new(),
+            executed: HashSet::new(),
+            strings: HashSet::new(),
+            diagnostics: Vec::new(),
+            reported: HashSet::new(),
+            calls: Vec::new(),
+            returns: Vec::new(),
+            halts: false,
+            computed: false,
+        };
+        walk.visit(self.f_x, self.f_y, self.f_dir, self.deep_sea, [None; 2]);
+        while let Some(s) = walk.queue.pop() {
+            walk.step(s);
+        }
+        walk
+    }
+
+    /// lint checks the codebox as it is now without running it, by walking every path the ><> could take from
+    /// where it is. Jumps are only followed when their coordinates are pushed by literals just before, and
+    /// anything else about the stacks is assumed to go either way. Diagnostics are sorted by position.
+    pub fn lint(&self) -> Vec<Diagnostic> {
+        let mut walk = self.walk();
+        if !walk.computed {
+            walk.unreachable();
+            if !walk.halts {
+                walk.diagnostics.push(Diagnostic {
+                    kind: LintKind::NoHalt,
+                    severity: Severity::Warning,
+                    x: self.f_x,
+                    y: self.f_y,
+                    length: 1,
+                    message: String::from(
+                        "no \";\" can be reached, so the ><> can't halt without an error",
+                    ),
+                });
+            }
+        }
+        walk.diagnostics.sort_by_key(|d| (d.y, d.x, d.kind));
+        walk.diagnostics
+    }
+}
@@ -0,0 +1,109 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::{CodeBox, StarfishError, Step, Value};
+
+/// Observer watches a CodeBox execute, for tools like coverage, visualizers and graders. It's given to a CodeBox
+/// with CodeBoxBuilder.Observer or CodeBox.SetObserver. Every method does nothing by default, so observers only
+/// implement the ones they need.
+///
+/// While an observer is attached, CodeBox.Run swims one instruction at a time rather than using its trace cache,
+/// so every instruction is seen. Without one, the only cost is checking that there isn't one.
+///
+/// Values moved between stacks by "[", "]" and "C", and changes made by custom instructions, aren't reported as
+/// pushes or pops.
+#[allow(unused_variables)]
+pub trait Observer {
+    /// on_step is called before the ><> executes the instruction it's on.
+    fn on_step(&mut self, codebox: &CodeBox) {}
+
+    /// on_instruction is called after the ><> has executed an instruction, with the result CodeBox.Swim returns.
+    fn on_instruction(&mut self, codebox: &CodeBox, result: &Result<Step, StarfishError>) {}
+
+    /// on_output is called with anything the ><> outputs.
+    fn on_output(&mut self, output: &str) {}
+
+    /// on_input is called with the value "i" pushes, which is -1 when there's no more input.
+    fn on_input(&mut self, value: &Value) {}
+
+    /// on_stack_push is called when value is pushed onto the stack at index stack.
+    fn on_stack_push(&mut self, stack: usize, value: &Value) {}
+
+    /// on_stack_pop is called when value is popped off the stack at index stack.
+    fn on_stack_pop(&mut self, stack: usize, value: &Value) {}
+
+    /// on_codebox_write is called when "p" writes new to x/y, which held old.
+    fn on_codebox_write(&mut self, x: i64, y: i64, old: &Value, new: &Value) {}
+
+    /// on_stack_create is called when "[" or "C" creates the stack at index stack.
+    fn on_stack_create(&mut self, stack: usize) {}
+
+    /// on_stack_close is called when "]" or "R" is about to remove the stack at index stack.
+    fn on_stack_close(&mut self, stack: usize) {}
+
+    /// on_halt is called when the ><> executes ";", or a custom instruction halts.
+    fn on_halt(&mut self, codebox: &CodeBox) {}
+}
+
+/// An Rc<RefCell<O>> observes with O, so the caller can keep a clone to read what O saw.
+impl<O: Observer> Observer for Rc<RefCell<O>> {
+    fn on_step(&mut self, codebox: &CodeBox) {
+        self.borrow_mut().on_step(codebox)
+    }
+
+    fn on_instruction(&mut self, codebox: &CodeBox, result: &Result<Step, StarfishError>) {
+        self.borrow_mut().on_instruction(codebox, result)
+    }
+
+    fn on_output(&mut self, output: &str) {
+        self.borrow_mut().on_output(output)
+    }
+
+    fn on_input(&mut self, value: &Value) {
+        self.borrow_mut().on_input(value)
+    }
+
+    fn on_stack_push(&mut self, stack: usize, value: &Value) {
+        self.borrow_mut().on_stack_push(stack, value)
+    }
+
+    fn on_stack_pop(&mut self, stack: usize, value: &Value) {
+        self.borrow_mut().on_stack_pop(stack, value)
+    }
+
+    fn on_codebox_write(&mut self, x: i64, y: i64, old: &Value, new: &Value) {
+        self.borrow_mut().on_codebox_write(x, y, old, new)
+    }
+
+    fn on_stack_create(&mut self, stack: usize) {
+        self.borrow_mut().on_stack_create(stack)
+    }
+
+    fn on_stack_close(&mut self, stack: usize) {
+        self.borrow_mut().on_stack_close(stack)
+    }
+
+    fn on_halt(&mut self, codebox: &CodeBox) {
+        self.borrow_mut().on_halt(codebox)
+    }
+}
+
+impl CodeBox {
+    /// set_observer attaches observer, replacing any observer already attached.
+    pub fn set_observer(&mut self, observer: impl Observer + 'static) {
+        self.observer = Some(Box::new(observer));
+    }
+
+    /// remove_observer detaches the observer and returns it, if there was one.
+    pub fn remove_observer(&mut self) -> Option<Box<dyn Observer>> {
+        self.observer.take()
+    }
+
+    /// observe calls f with the observer and the CodeBox, if there's an observer.
+    pub(crate) fn observe(&mut self, f: impl FnOnce(&mut dyn Observer, &CodeBox)) {
+        if let Some(mut observer) = self.observer.take() {
+            f(observer.as_mut(), self);
+            self.observer = Some(observer);
+        }
+    }
+}
@@ -0,0 +1,264 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+
+use crate::{CodeBox, Direction, Limits, RunReport, VIEW_SIZE};
+
+/// DIRECTIONS lists every Direction, in the order profiles report them.
+const DIRECTIONS: [Direction; 4] = [
+    Direction::Right,
+    Direction::Down,
+    Direction::Left,
+    Direction::Up,
+];
+
+/// ANSI_RAMP is the 256-colour palette the terminal heatmap shades cells with, from coldest to hottest.
+const ANSI_RAMP: [u8; 12] = [19, 25, 31, 37, 43, 49, 118, 190, 226, 214, 202, 196];
+
+/// Profile counts what the ><> executed during CodeBox.Profile.
+#[derive(Debug, Clone, Default)]
+pub struct Profile {
+    /// cells counts how many times the ><> executed each cell while swimming in each direction.
+    pub cells: HashMap<(i64, i64, Direction), u64>,
+    /// instructions counts how many times each instruction was executed. Cells pushed in string mode count too.
+    pub instructions: BTreeMap<char, u64>,
+    /// max_depth is the most values any stack held.
+    pub max_depth: usize,
+    /// max_stacks is the most stacks there were at once.
+    pub max_stacks: usize,
+    /// steps is how many instructions were executed, as RunReport.steps counts them.
+    pub steps: u64,
+}
+
+impl Profile {
+    /// count returns how many times the ><> executed x/y, in any direction.
+    pub fn count(&self, x: i64, y: i64) -> u64 {
+        DIRECTIONS
+            .iter()
+            .filter_map(|&dir| self.cells.get(&(x, y, dir)))
+            .sum()
+    }
+
+    /// max_count returns the most times any one cell was executed.
+    pub fn max_count(&self) -> u64 {
+        let mut totals: HashMap<(i64, i64), u64> = HashMap::new();
+        for (&(x, y, _), &n) in &self.cells {
+            *totals.entry((x, y)).or_default() += n;
+        }
+        totals.into_values().max().unwrap_or(0)
+    }
+
+    /// record counts the ><> executing r at x/y while swimming in dir.
+    fn record(&mut self, x: i64, y: i64, dir: Direction, r: char) {
+        *self.cells.entry((x, y, dir)).or_default() += 1;
+        *self.instructions.entry(r).or_default() += 1;
+        self.steps += 1;
+    }
+
+    /// record_stacks updates max_depth and max_stacks. Unless stacks were created, only the current stack can have
+    /// grown since the last step.
+    fn record_stacks(&mut self, codebox: &CodeBox) {
+        let stacks = codebox.stacks();
+        let depth = if stacks.len() > self.max_stacks {
+            self.max_stacks = stacks.len();
+            stacks.iter().map(|s| s.s.len()).max().unwrap_or(0)
+        } else {
+            stacks[codebox.current_stack()].s.len()
+        };
+        self.max_depth = self.max_depth.max(depth);
+    }
+
+    /// heat returns how hot a cell executed n times is, from 0 to 1, on a log scale so loops don't drown out
+    /// everything else.
+    fn heat(n: u64, max: u64) -> f64 {
+        if max == 0 {
+            return 0.0;
+        }
+        (n as f64).ln_1p() / (max as f64).ln_1p()
+    }
+
+    /// area returns the top left corner and the size of the part of the codebox the heatmaps show: the smallest
+    /// rectangle holding the script and every executed cell, cut down to VIEW_SIZE cells across and down. Cells "p"
+    /// wrote which were never executed don't stretch it.
+    fn area(&self, codebox: &CodeBox) -> ((i64, i64), (u64, u64)) {
+        let (width, height) = codebox.grid.dense_size();
+        let (mut min, mut max) = ((0, 0), (width - 1, height - 1));
+        for &(x, y, _) in self.cells.keys() {
+            min = (min.0.min(x), min.1.min(y));
+            max = (max.0.max(x), max.1.max(y));
+        }
+        let size = |lo: i64, hi: i64| (hi.abs_diff(lo) + 1).min(VIEW_SIZE);
+        (min, (size(min.0, max.0), size(min.1, max.1)))
+    }
+
+    /// ansi returns the cells in Profile.Area laid out as CodeBox.Print does, with every executed cell shaded by how
+    /// many times it was executed, for a terminal which supports 256 colours.
+    pub fn ansi(&self, codebox: &CodeBox) -> String {
+        let max = self.max_count();
+        let ((x0, y0), (width, height)) = self.area(codebox);
+        let mut out = String::new();
+        for y in y0..y0 + height as i64 {
+            for x in x0..x0 + width as i64 {
+                let r = display_char(codebox.cell_char(x, y));
+                match self.count(x, y) {
+                    0 => _ = write!(out, " {} ", r),
+                    n => {
+                        let i = (Profile::heat(n, max) * (ANSI_RAMP.len() - 1) as f64).round();
+                        let colour = ANSI_RAMP[i as usize];
+                        _ = write!(out, "\x1b[30;48;5;{}m {} \x1b[0m", colour, r);
+                    }
+                }
+            }
+            out.push('\n');
+        }
+        out
+    }
+
+    /// svg returns an SVG image of the cells in Profile.Area, with every executed cell shaded by how many times it
+    /// was executed. Hovering over a cell shows its counts.
+    pub fn svg(&self, codebox: &CodeBox) -> String {
+        const CELL: u64 = 20;
+        let max = self.max_count();
+        let ((x0, y0), (width, height)) = self.area(codebox);
+        let mut out = String::new();
+        _ = writeln!(
+            out,
+            r#"<svg xmlns="http://www.w3.org/2000/svg" width="{}" height="{}" font-family="monospace" font-size="14" text-anchor="middle">"#,
+            width * CELL,
+            height * CELL
+        );
+        for (row, y) in (y0..y0 + height as i64).enumerate() {
+            for (col, x) in (x0..x0 + width as i64).enumerate() {
+                let (px, py) = (col as u64 * CELL, row as u64 * CELL);
+                let r = display_char(codebox.cell_char(x, y));
+                let n = self.count(x, y);
+                let fill = if n == 0 {
+                    String::from("#fff")
+                } else {
+                    // From blue for the coldest to red for the hottest.
+                    format!(
+                        "hsl({:.0}, 90%, 60%)",
+                        240.0 * (1.0 - Profile::heat(n, max))
+                    )
+                };
+                _ = write!(out, r#"<g><title>({}, {}) {}"#, x, y, escape(r));
+                for dir in DIRECTIONS {
+                    if let Some(n) = self.cells.get(&(x, y, dir)) {
+                        _ = write!(out, "\n{}: {}", direction_name(dir), n);
+                    }
+                }
+                _ = writeln!(
+                    out,
+                    r#"</title><rect x="{}" y="{}" width="{}" height="{}" fill="{}"/><text x="{}" y="{}">{}</text></g>"#,
+                    px,
+                    py,
+                    CELL,
+                    CELL,
+                    fill,
+                    px + CELL / 2,
+                    py + CELL * 3 / 4,
+                    escape(r)
+                );
+            }
+        }
+        out.push_str("</svg>\n");
+        out
+    }
+
+    /// html returns a standalone HTML page holding the heatmap from Profile.Svg, the totals, and how many times
+    /// each instruction was executed.
+    pub fn html(&self, codebox: &CodeBox) -> String {
+        let mut out = String::from(
+            "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>starfish profile</title>\n</head>\n<body>\n",
+        );
+        out.push_str(&self.svg(codebox));
+        _ = writeln!(
+            out,
+            "<p>{} steps, at most {} values on a stack and {} stacks.</p>",
+            self.steps, self.max_depth, self.max_stacks
+        );
+        out.push_str("<table>\n<tr><th>Instruction</th><th>Executions</th></tr>\n");
+        for (r, n) in self.instructions_by_count() {
+            _ = writeln!(
+                out,
+                "<tr><td><code>{}</code></td><td>{}</td></tr>",
+                escape(display_char(r)),
+                n
+            );
+        }
+        out.push_str("</table>\n</body>\n</html>\n");
+        out
+    }
+
+    /// csv returns the raw counts, one row for each cell and direction the ><> executed, sorted by position.
+    pub fn csv(&self, codebox: &CodeBox) -> String {
+        let mut cells: Vec<_> = self.cells.iter().collect();
+        cells.sort_by_key(|&(&(x, y, dir), _)| (y, x, dir as u8));
+        let mut out = String::from("x,y,direction,instruction,count\n");
+        for (&(x, y, dir), n) in cells {
+            let r = codebox.cell_char(x, y).to_string();
+            let r = if r == "\"" || r == "," || r == "\n" || r == "\r" {
+                format!("\"{}\"", r.replace('"', "\"\""))
+            } else {
+                r
+            };
+            _ = writeln!(out, "{},{},{},{},{}", x, y, direction_name(dir), r, n);
+        }
+        out
+    }
+
+    /// instructions_by_count returns every instruction executed, most executed first.
+    pub fn instructions_by_count(&self) -> Vec<(char, u64)> {
+        let mut instructions: Vec<_> = self.instructions.iter().map(|(&r, &n)| (r, n)).collect();
+        instructions.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        instructions
+    }
+}
+
+/// display_char returns r, or a space if it's a control character which would garble the output.
+fn display_char(r: char) -> char {
+    if r.is_control() {
+        ' '
+    } else {
+        r
+    }
+}
+
+/// direction_name returns the name of dir as it appears in profiles.
+fn direction_name(dir: Direction) -> &'static str {
+    match dir {
+        Direction::Right => "right",
+        Direction::Down => "down",
+        Direction::Left => "left",
+        Direction::Up => "up",
+    }
+}
+
+/// escape returns r escaped for HTML and SVG.
+fn escape(r: char) -> String {
+    match r {
+        '&' => String::from("&amp;"),
+        '<' => String::from("&lt;"),
+        '>' => String::from("&gt;"),
+        '"' => String::from("&quot;"),
+        r => r.to_string(),
+    }
+}
+
+impl CodeBox {
+    /// profile runs like CodeBox.Run, while counting what the ><> executes. Every instruction is swum one at a
+    /// time so each can be counted, which is slower than CodeBox.Run.
+    pub fn profile(&mut self, limits: Limits) -> (RunReport, Profile) {
+        let mut profile = Profile::default();
+        profile.record_stacks(self);
+        let report = self.run_with(limits, |codebox, _, steps| {
+            let (x, y, dir) = (codebox.f_x, codebox.f_y, codebox.f_dir);
+            let r = codebox.cell_char(x, y);
+            let step = codebox.swim()?;
+            *steps += 1;
+            profile.record(x, y, dir, r);
+            profile.record_stacks(codebox);
+            Ok(step)
+        });
+        (report, profile)
+    }
+}
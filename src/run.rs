@@ -0,0 +1,101 @@
+use std::time::{Duration, Instant};
+
+use crate::{CodeBox, Stack, StarfishError, Step};
+
+/// Limits caps how much work CodeBox.Run may do. A limit of None means unlimited.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Limits {
+    /// max_steps is the most instructions the ><> may execute.
+    pub max_steps: Option<u64>,
+    /// max_time is the most wall-clock time the run may take, including time spent sleeping for "S", which is cut
+    /// short rather than sleeping past it.
+    pub max_time: Option<Duration>,
+    /// max_output is the most bytes of output which will be collected.
+    pub max_output: Option<usize>,
+}
+
+/// ExitReason is why CodeBox.Run stopped.
+#[derive(Debug)]
+pub enum ExitReason {
+    /// The ><> executed ";".
+    Halted,
+    /// Something smelled fishy.
+    Error(StarfishError),
+    /// Limits.max_steps was reached.
+    StepLimit,
+    /// Limits.max_time was reached.
+    TimeLimit,
+    /// Limits.max_output was reached. The collected output is truncated to the limit.
+    OutputLimit,
+}
+
+/// RunReport is the result of CodeBox.Run.
+#[derive(Debug)]
+pub struct RunReport {
+    /// output is everything the ><> output during the run.
+    pub output: Vec<u8>,
+    pub exit: ExitReason,
+    /// steps is how many instructions were executed, counting the final ";" but not one which failed.
+    pub steps: u64,
+    /// stacks is a copy of every stack when the run stopped, bottom first.
+    pub stacks: Vec<Stack>,
+}
+
+impl CodeBox {
+    /// run swims until the ><> halts, fails, or hits one of limits. Output is still written to the CodeBox's
+    /// StarfishIo as it happens, as well as being collected into the report, and no more than Limits.max_output
+    /// bytes of it are written to either.
+    pub fn run(&mut self, limits: Limits) -> RunReport {
+        self.run_with(limits, CodeBox::swim_trace)
+    }
+
+    /// run_with runs like CodeBox.Run, calling swim to move the ><> on. swim is given the most steps it may take
+    /// and adds the number it took to the last argument.
+    pub(crate) fn run_with(
+        &mut self,
+        limits: Limits,
+        mut swim: impl FnMut(&mut CodeBox, u64, &mut u64) -> Result<Step, StarfishError>,
+    ) -> RunReport {
+        let start = Instant::now();
+        let mut output = Vec::new();
+        let mut steps = 0;
+        self.output_budget = limits.max_output;
+        self.deadline = limits.max_time.and_then(|max| start.checked_add(max));
+
+        let exit = loop {
+            if limits.max_steps.is_some_and(|max| steps >= max) {
+                break ExitReason::StepLimit;
+            }
+            if limits.max_time.is_some_and(|max| start.elapsed() >= max) {
+                break ExitReason::TimeLimit;
+            }
+
+            let max_steps = limits.max_steps.map_or(u64::MAX, |max| max - steps);
+            match swim(self, max_steps, &mut steps) {
+                Ok(step) => match step {
+                    Step::Halt => break ExitReason::Halted,
+                    Step::Output(s) => {
+                        output.extend_from_slice(s.as_bytes());
+                        if let Some(max) = limits.max_output {
+                            if output.len() > max {
+                                output.truncate(max);
+                                break ExitReason::OutputLimit;
+                            }
+                        }
+                    }
+                    Step::Continue | Step::Sleep(_) => {}
+                },
+                Err(e) => break ExitReason::Error(e),
+            }
+        };
+        self.output_budget = None;
+        self.deadline = None;
+
+        RunReport {
+            output,
+            exit,
+            steps,
+            stacks: self.stacks.clone(),
+        }
+    }
+}
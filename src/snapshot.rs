@@ -0,0 +1,128 @@
+use std::error::Error;
+
+use bincode::Options;
+use serde::{Deserialize, Serialize};
+
+use rand_chacha::ChaCha8Rng;
+
+use crate::grid::Grid;
+use crate::{Clock, CodeBox, Dialect, Direction, Encoding, NumericMode, Stack};
+
+/// Snapshot is the complete state of a CodeBox at some point in its run: the ><>, its modes, every stack and
+/// register, the codebox as it's been changed by "p", its Clock and the state of the random number generator used
+/// by "x". It's created by CodeBox.Snapshot and applied with
+/// CodeBox.Restore.
+///
+/// The StarfishIo, the FileSystem, and any file opened with "F", aren't part of a snapshot.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Snapshot {
+    x: i64,
+    y: i64,
+    direction: Direction,
+    was_left: bool,
+    escaped_hook: bool,
+    string_mode: u8,
+    deep_sea: bool,
+    dialect: Dialect,
+    numeric_mode: NumericMode,
+    encoding: Encoding,
+    stacks: Vec<Stack>,
+    current_stack: usize,
+    grid: Grid,
+    clock: Clock,
+    rng: ChaCha8Rng,
+}
+
+impl Snapshot {
+    /// to_json returns the snapshot as JSON.
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("snapshots are always serializable")
+    }
+
+    /// from_json parses a snapshot written by Snapshot.ToJson.
+    pub fn from_json(json: &str) -> Result<Snapshot, Box<dyn Error>> {
+        serde_json::from_str::<Snapshot>(json)?.check()
+    }
+
+    /// to_bytes returns the snapshot in a compact binary format, bincode with variable length integers.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        bincode::options()
+            .serialize(self)
+            .expect("snapshots are always serializable")
+    }
+
+    /// from_bytes parses a snapshot written by Snapshot.ToBytes.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Snapshot, Box<dyn Error>> {
+        bincode::options().deserialize::<Snapshot>(bytes)?.check()
+    }
+
+    /// check returns the snapshot if it describes a CodeBox which could exist, so restoring it can't leave the
+    /// ><> somewhere it can't swim.
+    fn check(self) -> Result<Snapshot, Box<dyn Error>> {
+        self.grid.check()?;
+        if !self.grid.contains(self.x, self.y) {
+            return Err("the ><> is outside of the codebox")?;
+        }
+        if self.current_stack >= self.stacks.len() {
+            return Err("the current stack doesn't exist")?;
+        }
+        let modes_match = self.stacks.iter().all(|stack| {
+            stack
+                .s
+                .iter()
+                .chain(stack.register_value())
+                .chain(self.grid.values())
+                .all(|v| v.mode() == self.numeric_mode)
+        });
+        if !modes_match {
+            return Err("a value doesn't match the numeric mode")?;
+        }
+        Ok(self)
+    }
+}
+
+impl CodeBox {
+    /// snapshot returns a copy of the CodeBox's complete state.
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            x: self.f_x,
+            y: self.f_y,
+            direction: self.f_dir,
+            was_left: self.was_left,
+            escaped_hook: self.escaped_hook,
+            string_mode: self.string_mode,
+            deep_sea: self.deep_sea,
+            dialect: self.dialect,
+            numeric_mode: self.numeric_mode,
+            encoding: self.encoding,
+            stacks: self.stacks.clone(),
+            current_stack: self.p,
+            grid: self.grid.clone(),
+            clock: self.clock,
+            rng: self.rng.clone(),
+        }
+    }
+
+    /// restore puts the CodeBox back into the state captured by snapshot, which may have come from a different
+    /// CodeBox. The CodeBox keeps its own StarfishIo and FileSystem, and closes any file opened with "F".
+    pub fn restore(&mut self, snapshot: &Snapshot) {
+        self.f_x = snapshot.x;
+        self.f_y = snapshot.y;
+        self.f_dir = snapshot.direction;
+        self.was_left = snapshot.was_left;
+        self.escaped_hook = snapshot.escaped_hook;
+        self.string_mode = snapshot.string_mode;
+        self.deep_sea = snapshot.deep_sea;
+        self.dialect = snapshot.dialect;
+        self.numeric_mode = snapshot.numeric_mode;
+        self.encoding = snapshot.encoding;
+        self.stacks = snapshot.stacks.clone();
+        self.p = snapshot.current_stack;
+        self.grid = snapshot.grid.clone();
+        self.clock = snapshot.clock;
+        self.rng = snapshot.rng.clone();
+        self.file = None;
+        self.traces = Vec::new();
+        self.generation += 1;
+    }
+}
@@ -0,0 +1,268 @@
+use std::rc::Rc;
+
+use crate::{mirror, CodeBox, Dialect, Direction, Instruction, StarfishError, Step};
+
+/// MAX_TRACE_LEN caps how many instructions a trace holds, so long straight runs are split up.
+const MAX_TRACE_LEN: usize = 256;
+
+/// Op is a decoded instruction. Everything about it which only depends on where the ><> is and which way it's
+/// swimming has been worked out when the trace was compiled.
+#[derive(Debug, Clone, Copy)]
+enum Op {
+    /// A run of cells which do nothing, like " ", all swum through in one go.
+    Nop(u32),
+    /// "!". The cell it skips is already left out of the trace.
+    Skip,
+    /// An arrow or mirror, with the direction it leaves the ><> swimming in and the new was_left, if it changes.
+    Turn(Direction, Option<bool>),
+    /// "?". The trace continues as though the value popped isn't zero, and exits otherwise.
+    Branch,
+    Push(i64),
+    Arithmetic(u8),
+    Compare(u8),
+    /// Any other instruction which leaves the ><> where it is. The trace exits if it produces anything but
+    /// Step::Continue.
+    Exec(u8),
+    /// An instruction which moves the ><> or changes its mode unpredictably. It's always the last in a trace.
+    Exit(u8),
+}
+
+/// TraceOp is an Op along with the cell it came from.
+#[derive(Debug)]
+struct TraceOp {
+    op: Op,
+    x: i64,
+    y: i64,
+}
+
+/// Trace is a run of instructions the ><> always swims through in order from some cell and direction, up to the
+/// first one which can send it somewhere unpredictable, or the start of another trace.
+#[derive(Debug)]
+pub(crate) struct Trace {
+    ops: Vec<TraceOp>,
+    /// steps is how many instructions the trace executes, which is more than ops.len() if any runs were merged.
+    steps: u64,
+    /// end is the cell the ><> swims to after the last op.
+    end: (i64, i64),
+    /// generation is the CodeBox's generation when the trace was compiled. The trace is stale once they differ.
+    generation: u64,
+}
+
+/// decode returns the Op for the instruction r, hit while swimming in dir.
+fn decode(r: u8, dir: Direction, dialect: Dialect, deep_sea: bool) -> Op {
+    let instruction = match Instruction::from_byte(r) {
+        Some(instruction) if dialect.has(instruction) => instruction,
+        // Unknown instructions are ignored in deep-sea mode, like anything else which isn't movement.
+        None if deep_sea => return Op::Nop(1),
+        // CodeBox.Exe reports the invalid instruction.
+        _ => return Op::Exec(r),
+    };
+    let info = instruction.info();
+    use Instruction::*;
+    match instruction {
+        _ if deep_sea && !info.deep_sea => Op::Nop(1),
+        // CodeBox.compile_trace has already turned the string between a pair of quotes into pushes.
+        Nop | DoubleQuote | SingleQuote => Op::Nop(1),
+        Random | Hook | Surface => Op::Exit(r),
+        _ if info.turns => {
+            let (dir, was_left) = mirror(r, dir);
+            Op::Turn(dir, was_left)
+        }
+        Skip => Op::Skip,
+        SkipIfZero => Op::Branch,
+        Push0 | Push1 | Push2 | Push3 | Push4 | Push5 | Push6 | Push7 | Push8 | Push9 | PushA
+        | PushB | PushC | PushD | PushE | PushF => Op::Push(instruction.digit().unwrap() as i64),
+        Add | Subtract | Multiply | Divide | Modulo => Op::Arithmetic(r),
+        Equal | Greater | Less => Op::Compare(r),
+        Jump | Call | Return | DeepSea | Put | File | Halt => Op::Exit(r),
+        _ => Op::Exec(r),
+    }
+}
+
+impl CodeBox {
+    /// trace_index returns where the trace starting at x/y, swimming in dir, lives in CodeBox.traces. Only cells
+    /// in the grid's dense area have traces.
+    fn trace_index(&self, x: i64, y: i64, dir: Direction) -> Option<usize> {
+        let cell = self.grid.dense_index(x, y)?;
+        Some((cell * 4 + dir as usize) * 2 + self.deep_sea as usize)
+    }
+
+    /// compile_trace decodes the instructions the ><> will swim through from its current cell and direction.
+    fn compile_trace(&mut self) -> Trace {
+        let mut ops: Vec<TraceOp> = Vec::new();
+        let mut steps = 0;
+        let (mut x, mut y, mut dir) = (self.f_x, self.f_y, self.f_dir);
+        // The trace stops short of swimming through a cell twice, so it never unrolls a loop. Instead, another
+        // trace starting at the top of the loop goes around it once. Cells are marked as seen by setting their
+        // entry in trace_marks to a number unique to this compilation.
+        self.trace_mark += 1;
+        while ops.len() < MAX_TRACE_LEN {
+            // The trace ends where the ><> swims out of the dense area.
+            let Some(index) = self.trace_index(x, y, dir) else {
+                break;
+            };
+            let joins = matches!(&self.traces[index], Some(t) if t.generation == self.generation);
+            if self.trace_marks[index] == self.trace_mark || (joins && steps > 0) {
+                break;
+            }
+            self.trace_marks[index] = self.trace_mark;
+
+            // Cells holding values other than bytes are left for CodeBox.Swim.
+            if self.grid.value(x, y).is_some() {
+                break;
+            }
+            let r = self.grid.get(x, y);
+            let mut push = |op: Op, x: i64, y: i64| {
+                steps += 1;
+                match (ops.last_mut(), op) {
+                    (Some(TraceOp { op: Op::Nop(n), .. }), Op::Nop(1)) => *n += 1,
+                    _ => ops.push(TraceOp { op, x, y }),
+                }
+            };
+            if (r == b'"' || r == b'\'') && !self.deep_sea && !self.registry.contains(r) {
+                // Nothing is interpreted in string mode, so the whole string can be pushed without ever entering
+                // it. The closing quote might be the opening one, if the string wraps all the way around.
+                let Some(string) = self.string_at(x, y, dir) else {
+                    break;
+                };
+                push(Op::Nop(1), x, y);
+                for (x, y) in string {
+                    push(Op::Push(self.grid.get(x, y) as i64), x, y);
+                }
+                (x, y) = self.string_end(x, y, dir);
+            }
+            let op = match self.grid.get(x, y) {
+                // A custom instruction could do anything, even jump.
+                r if self.registry.contains(r) => Op::Exit(r),
+                r => decode(r, dir, self.dialect, self.deep_sea),
+            };
+            push(op, x, y);
+            match op {
+                Op::Exit(_) => break,
+                Op::Turn(d, _) => dir = d,
+                _ => {}
+            }
+            (x, y) = self.grid.next(x, y, dir);
+            if let Op::Skip = op {
+                (x, y) = self.grid.next(x, y, dir);
+            }
+        }
+        Trace {
+            ops,
+            steps,
+            end: (x, y),
+            generation: self.generation,
+        }
+    }
+
+    /// string_at returns the cells between the quote at x/y and the one closing it, if they're all bytes in the
+    /// dense area.
+    fn string_at(&self, x: i64, y: i64, dir: Direction) -> Option<Vec<(i64, i64)>> {
+        let quote = self.grid.get(x, y);
+        let mut cells = Vec::new();
+        let (mut x, mut y) = self.grid.next(x, y, dir);
+        while self.grid.get(x, y) != quote {
+            self.grid.dense_index(x, y)?;
+            if self.grid.value(x, y).is_some() {
+                return None;
+            }
+            cells.push((x, y));
+            (x, y) = self.grid.next(x, y, dir);
+        }
+        self.grid.dense_index(x, y)?;
+        Some(cells)
+    }
+
+    /// string_end returns the closing quote of the string opened by the quote at x/y.
+    fn string_end(&self, x: i64, y: i64, dir: Direction) -> (i64, i64) {
+        let quote = self.grid.get(x, y);
+        let (mut x, mut y) = self.grid.next(x, y, dir);
+        while self.grid.get(x, y) != quote {
+            (x, y) = self.grid.next(x, y, dir);
+        }
+        (x, y)
+    }
+
+    /// swim_trace is CodeBox.Swim, except that it executes as much of a cached trace as it can, up to max_steps
+    /// instructions. steps is increased by how many instructions were executed successfully.
+    pub(crate) fn swim_trace(
+        &mut self,
+        max_steps: u64,
+        steps: &mut u64,
+    ) -> Result<Step, StarfishError> {
+        if !self.trace_cache || self.string_mode != 0 || self.observer.is_some() {
+            let step = self.swim()?;
+            *steps += 1;
+            return Ok(step);
+        }
+
+        let Some(index) = self.trace_index(self.f_x, self.f_y, self.f_dir) else {
+            let step = self.swim()?;
+            *steps += 1;
+            return Ok(step);
+        };
+        if self.traces.is_empty() {
+            self.traces = vec![None; self.grid.dense_len() * 8];
+            self.trace_marks = vec![0; self.traces.len()];
+        }
+        let trace = match &self.traces[index] {
+            Some(trace) if trace.generation == self.generation => trace.clone(),
+            _ => {
+                let trace = Rc::new(self.compile_trace());
+                self.traces[index] = Some(trace.clone());
+                trace
+            }
+        };
+        if trace.steps == 0 || trace.steps > max_steps {
+            let step = self.swim()?;
+            *steps += 1;
+            return Ok(step);
+        }
+
+        for op in &trace.ops {
+            self.f_x = op.x;
+            self.f_y = op.y;
+            match op.op {
+                Op::Nop(n) => {
+                    *steps += n as u64;
+                    continue;
+                }
+                Op::Skip => {}
+                Op::Turn(dir, was_left) => {
+                    self.f_dir = dir;
+                    if let Some(was_left) = was_left {
+                        self.was_left = was_left;
+                    }
+                }
+                Op::Branch => {
+                    if self.pop()?.is_zero() {
+                        *steps += 1;
+                        self.shift();
+                        self.shift();
+                        return Ok(Step::Continue);
+                    }
+                }
+                Op::Push(i) => self.push_int(i),
+                Op::Arithmetic(r) => self.arithmetic(r)?,
+                Op::Compare(r) => self.compare(r)?,
+                Op::Exec(r) => {
+                    let step = self.exe(r)?;
+                    if !matches!(step, Step::Continue) {
+                        *steps += 1;
+                        self.shift();
+                        return Ok(step);
+                    }
+                }
+                Op::Exit(r) => {
+                    let step = self.exe(r)?;
+                    *steps += 1;
+                    self.shift();
+                    return Ok(step);
+                }
+            }
+            *steps += 1;
+        }
+        (self.f_x, self.f_y) = trace.end;
+        Ok(Step::Continue)
+    }
+}
@@ -0,0 +1,362 @@
+use std::cmp::Ordering;
+use std::{fmt, str};
+
+use num_bigint::BigInt;
+use num_rational::BigRational;
+use num_traits::{Euclid, FromPrimitive, Signed, ToPrimitive, Zero};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/// NumericMode chooses how a CodeBox represents the values on its stacks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum NumericMode {
+    /// 64-bit floats, as the language has always used. Integers beyond 2^53 lose precision.
+    #[default]
+    Float,
+    /// 64-bit integers. Overflow is an error, and "," is Euclidean division to match "%".
+    Integer,
+    /// Exact fractions of arbitrarily large integers.
+    Rational,
+    /// Arbitrarily large integers. "," is Euclidean division to match "%".
+    BigInteger,
+}
+
+impl NumericMode {
+    /// int returns i as a value in this mode.
+    pub fn int(self, i: i64) -> Value {
+        match self {
+            NumericMode::Float => Value::Float(i as f64),
+            NumericMode::Integer => Value::Int(i),
+            NumericMode::Rational => Value::Rational(Box::new(BigRational::from_integer(i.into()))),
+            NumericMode::BigInteger => Value::BigInt(Box::new(i.into())),
+        }
+    }
+
+    /// convert returns v as a value in this mode. Fractions are truncated when converting to an integer mode,
+    /// and values which don't fit an Integer saturate.
+    pub fn convert(self, v: Value) -> Value {
+        match (self, v) {
+            (NumericMode::Float, Value::Float(f)) => Value::Float(f),
+            (NumericMode::Float, v) => Value::Float(v.to_f64()),
+            (NumericMode::Integer, Value::Int(i)) => Value::Int(i),
+            (NumericMode::Integer, Value::Float(f)) => Value::Int(f as i64),
+            (NumericMode::Integer, v) => {
+                let i = v.to_big_int();
+                Value::Int(
+                    i.to_i64()
+                        .unwrap_or(if i.is_negative() { i64::MIN } else { i64::MAX }),
+                )
+            }
+            (NumericMode::Rational, Value::Rational(r)) => Value::Rational(r),
+            (NumericMode::Rational, Value::Float(f)) => Value::Rational(Box::new(
+                BigRational::from_float(f).unwrap_or_else(BigRational::zero),
+            )),
+            (NumericMode::Rational, v) => {
+                Value::Rational(Box::new(BigRational::from_integer(v.to_big_int())))
+            }
+            (NumericMode::BigInteger, Value::BigInt(i)) => Value::BigInt(i),
+            (NumericMode::BigInteger, v) => Value::BigInt(Box::new(v.to_big_int())),
+        }
+    }
+}
+
+impl str::FromStr for NumericMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<NumericMode, String> {
+        match s.to_ascii_lowercase().as_str() {
+            "float" => Ok(NumericMode::Float),
+            "integer" | "int" => Ok(NumericMode::Integer),
+            "rational" => Ok(NumericMode::Rational),
+            "biginteger" | "bigint" => Ok(NumericMode::BigInteger),
+            _ => Err(format!(
+                "unknown numeric mode {:?} (expected float, integer, rational or bigint)",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for NumericMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            NumericMode::Float => "float",
+            NumericMode::Integer => "integer",
+            NumericMode::Rational => "rational",
+            NumericMode::BigInteger => "bigint",
+        })
+    }
+}
+
+/// ArithmeticError is why an arithmetic instruction failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArithmeticError {
+    DivisionByZero,
+    Overflow,
+}
+
+/// Value is a single value on a stack. Every value in a CodeBox uses the variant matching its NumericMode. The
+/// arbitrary precision variants are boxed, so the common ones stay small.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Value {
+    Float(#[serde(with = "float")] f64),
+    Int(i64),
+    Rational(Box<BigRational>),
+    BigInt(Box<BigInt>),
+}
+
+impl Value {
+    /// mode returns the NumericMode v belongs to.
+    pub fn mode(&self) -> NumericMode {
+        match self {
+            Value::Float(_) => NumericMode::Float,
+            Value::Int(_) => NumericMode::Integer,
+            Value::Rational(_) => NumericMode::Rational,
+            Value::BigInt(_) => NumericMode::BigInteger,
+        }
+    }
+
+    /// to_f64 returns v as a float, which may be inexact or infinite.
+    pub fn to_f64(&self) -> f64 {
+        match self {
+            Value::Float(f) => *f,
+            Value::Int(i) => *i as f64,
+            Value::Rational(r) => r.to_f64().unwrap_or(f64::NAN),
+            Value::BigInt(i) => i.to_f64().unwrap_or(f64::NAN),
+        }
+    }
+
+    /// to_big_int returns v truncated to an integer. Non-finite floats become 0.
+    pub fn to_big_int(&self) -> BigInt {
+        match self {
+            Value::Float(f) => BigInt::from_f64(f.trunc()).unwrap_or_default(),
+            Value::Int(i) => (*i).into(),
+            Value::Rational(r) => r.to_integer(),
+            Value::BigInt(i) => (**i).clone(),
+        }
+    }
+
+    /// to_rational returns v as an exact fraction, or None if it's NaN or infinite.
+    fn to_rational(&self) -> Option<BigRational> {
+        match self {
+            Value::Float(f) => BigRational::from_float(*f),
+            Value::Int(i) => Some(BigRational::from_integer((*i).into())),
+            Value::Rational(r) => Some((**r).clone()),
+            Value::BigInt(i) => Some(BigRational::from_integer((**i).clone())),
+        }
+    }
+
+    /// to_byte returns v if it's a whole number from 0 to 255. -0.0 isn't one, so it can be told apart from 0.
+    pub fn to_byte(&self) -> Option<u8> {
+        match self {
+            Value::Float(f) if f.fract() == 0.0 && f.is_sign_positive() && *f <= 255.0 => {
+                Some(*f as u8)
+            }
+            Value::Float(_) => None,
+            Value::Int(i) => u8::try_from(*i).ok(),
+            Value::Rational(r) if r.is_integer() => u8::try_from(r.to_integer()).ok(),
+            Value::Rational(_) => None,
+            Value::BigInt(i) => u8::try_from(&**i).ok(),
+        }
+    }
+
+    /// is_zero returns whether v is zero.
+    pub fn is_zero(&self) -> bool {
+        match self {
+            Value::Float(f) => *f == 0.0,
+            Value::Int(i) => *i == 0,
+            Value::Rational(r) => r.is_zero(),
+            Value::BigInt(i) => i.is_zero(),
+        }
+    }
+
+    /// checked_add implements "+".
+    pub fn checked_add(self, rhs: Value) -> Result<Value, ArithmeticError> {
+        match self.pair(rhs) {
+            (Value::Float(a), Value::Float(b)) => Ok(Value::Float(a + b)),
+            (Value::Int(a), Value::Int(b)) => a
+                .checked_add(b)
+                .map(Value::Int)
+                .ok_or(ArithmeticError::Overflow),
+            (Value::Rational(a), Value::Rational(b)) => Ok(Value::Rational(Box::new(*a + *b))),
+            (Value::BigInt(a), Value::BigInt(b)) => Ok(Value::BigInt(Box::new(*a + *b))),
+            _ => unreachable!(),
+        }
+    }
+
+    /// checked_sub implements "-".
+    pub fn checked_sub(self, rhs: Value) -> Result<Value, ArithmeticError> {
+        match self.pair(rhs) {
+            (Value::Float(a), Value::Float(b)) => Ok(Value::Float(a - b)),
+            (Value::Int(a), Value::Int(b)) => a
+                .checked_sub(b)
+                .map(Value::Int)
+                .ok_or(ArithmeticError::Overflow),
+            (Value::Rational(a), Value::Rational(b)) => Ok(Value::Rational(Box::new(*a - *b))),
+            (Value::BigInt(a), Value::BigInt(b)) => Ok(Value::BigInt(Box::new(*a - *b))),
+            _ => unreachable!(),
+        }
+    }
+
+    /// checked_mul implements "*".
+    pub fn checked_mul(self, rhs: Value) -> Result<Value, ArithmeticError> {
+        match self.pair(rhs) {
+            (Value::Float(a), Value::Float(b)) => Ok(Value::Float(a * b)),
+            (Value::Int(a), Value::Int(b)) => a
+                .checked_mul(b)
+                .map(Value::Int)
+                .ok_or(ArithmeticError::Overflow),
+            (Value::Rational(a), Value::Rational(b)) => Ok(Value::Rational(Box::new(*a * *b))),
+            (Value::BigInt(a), Value::BigInt(b)) => Ok(Value::BigInt(Box::new(*a * *b))),
+            _ => unreachable!(),
+        }
+    }
+
+    /// checked_div implements ",". Integer modes use Euclidean division, to match "%".
+    pub fn checked_div(self, rhs: Value) -> Result<Value, ArithmeticError> {
+        if rhs.is_zero() {
+            return Err(ArithmeticError::DivisionByZero);
+        }
+        match self.pair(rhs) {
+            (Value::Float(a), Value::Float(b)) => Ok(Value::Float(a / b)),
+            (Value::Int(a), Value::Int(b)) => a
+                .checked_div_euclid(b)
+                .map(Value::Int)
+                .ok_or(ArithmeticError::Overflow),
+            (Value::Rational(a), Value::Rational(b)) => Ok(Value::Rational(Box::new(*a / *b))),
+            (Value::BigInt(a), Value::BigInt(b)) => Ok(Value::BigInt(Box::new(a.div_euclid(&b)))),
+            _ => unreachable!(),
+        }
+    }
+
+    /// checked_rem implements "%". The result is never negative.
+    pub fn checked_rem(self, rhs: Value) -> Result<Value, ArithmeticError> {
+        if rhs.is_zero() {
+            return Err(ArithmeticError::DivisionByZero);
+        }
+        match self.pair(rhs) {
+            (Value::Float(a), Value::Float(b)) => Ok(Value::Float(a.rem_euclid(b))),
+            (Value::Int(a), Value::Int(b)) => a
+                .checked_rem_euclid(b)
+                .map(Value::Int)
+                .ok_or(ArithmeticError::Overflow),
+            (Value::Rational(a), Value::Rational(b)) => {
+                let b = b.abs();
+                let q = (&*a / &b).floor();
+                Ok(Value::Rational(Box::new(*a - b * q)))
+            }
+            (Value::BigInt(a), Value::BigInt(b)) => Ok(Value::BigInt(Box::new(a.rem_euclid(&b)))),
+            _ => unreachable!(),
+        }
+    }
+
+    /// pair converts rhs to self's mode, so binary operations only see matching variants.
+    fn pair(self, rhs: Value) -> (Value, Value) {
+        let rhs = self.mode().convert(rhs);
+        (self, rhs)
+    }
+}
+
+/// float (de)serializes a Value::Float. Formats like JSON can't represent NaN or the infinities, so human-readable
+/// formats write them as the strings "nan", "inf" and "-inf".
+mod float {
+    use super::*;
+    use serde::de::Error;
+
+    pub fn serialize<S: Serializer>(f: &f64, serializer: S) -> Result<S::Ok, S::Error> {
+        if f.is_finite() || !serializer.is_human_readable() {
+            serializer.serialize_f64(*f)
+        } else {
+            serializer.serialize_str(&Value::Float(*f).to_string())
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<f64, D::Error> {
+        if !deserializer.is_human_readable() {
+            return f64::deserialize(deserializer);
+        }
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Float {
+            Number(f64),
+            String(String),
+        }
+        match Float::deserialize(deserializer)? {
+            Float::Number(f) => Ok(f),
+            Float::String(s) => match s.as_str() {
+                "nan" => Ok(f64::NAN),
+                "inf" => Ok(f64::INFINITY),
+                "-inf" => Ok(f64::NEG_INFINITY),
+                _ => Err(D::Error::custom(format!("invalid float {:?}", s))),
+            },
+        }
+    }
+}
+
+impl PartialEq for Value {
+    fn eq(&self, other: &Value) -> bool {
+        self.partial_cmp(other) == Some(Ordering::Equal)
+    }
+}
+
+impl PartialOrd for Value {
+    fn partial_cmp(&self, other: &Value) -> Option<Ordering> {
+        match (self, other) {
+            (Value::Float(a), Value::Float(b)) => a.partial_cmp(b),
+            (Value::Int(a), Value::Int(b)) => a.partial_cmp(b),
+            (Value::Rational(a), Value::Rational(b)) => a.partial_cmp(b),
+            (Value::BigInt(a), Value::BigInt(b)) => a.partial_cmp(b),
+            // Values from different modes are compared exactly, rather than converting one to the other's mode and
+            // rounding it, so the order is the same whichever way round they are.
+            _ => match (self.to_rational(), other.to_rational()) {
+                (Some(a), Some(b)) => a.partial_cmp(&b),
+                // Only floats can be NaN, which is unordered, or infinite, which is beyond every fraction.
+                (None, Some(_)) => self.to_f64().partial_cmp(&0.0),
+                (Some(_), None) => 0.0.partial_cmp(&other.to_f64()),
+                (None, None) => self.to_f64().partial_cmp(&other.to_f64()),
+            },
+        }
+    }
+}
+
+impl From<f64> for Value {
+    fn from(f: f64) -> Value {
+        Value::Float(f)
+    }
+}
+
+/// fmt_float formats a non-integral float like Python's repr, which is how the reference interpreter outputs it.
+fn fmt_float(f: f64, out: &mut fmt::Formatter<'_>) -> fmt::Result {
+    if f.is_nan() {
+        return out.write_str("nan");
+    }
+    if f.is_infinite() {
+        return out.write_str(if f > 0.0 { "inf" } else { "-inf" });
+    }
+    let exp = f.abs().log10().floor();
+    if (-4.0..16.0).contains(&exp) {
+        return write!(out, "{}", f);
+    }
+    // Rust writes 1e-5 where Python writes 1e-05.
+    let s = format!("{:e}", f);
+    let (mantissa, exp) = s.split_once('e').unwrap();
+    let (sign, digits) = match exp.strip_prefix('-') {
+        Some(digits) => ('-', digits),
+        None => ('+', exp),
+    };
+    write!(out, "{}e{}{:0>2}", mantissa, sign, digits)
+}
+
+impl fmt::Display for Value {
+    /// Integral values are written without a fractional part, as "n" outputs them.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Float(v) if v.fract() == 0.0 && v.abs() < 1e16 => write!(f, "{}", *v as i64),
+            Value::Float(v) if v.fract() == 0.0 => write!(f, "{}", BigInt::from_f64(*v).unwrap()),
+            Value::Float(v) => fmt_float(*v, f),
+            Value::Int(i) => write!(f, "{}", i),
+            Value::Rational(r) if r.is_integer() => write!(f, "{}", r.to_integer()),
+            Value::Rational(r) => fmt_float(r.to_f64().unwrap_or(f64::NAN), f),
+            Value::BigInt(i) => write!(f, "{}", i),
+        }
+    }
+}
@@ -0,0 +1,140 @@
+use std::sync::mpsc::Sender;
+
+use wasm_bindgen::prelude::*;
+
+use crate::{ChannelIo, CodeBox, Stack, Step};
+
+/// Report is what StarfishVM.step and StarfishVM.runFor return.
+#[wasm_bindgen(getter_with_clone)]
+#[derive(Debug, Clone, Default)]
+pub struct Report {
+    /// output is everything the ><> output.
+    pub output: String,
+    /// sleep is how many milliseconds the ><> asked to sleep for with "S", or 0. The VM stops as soon as the ><>
+    /// sleeps, leaving the waiting to the caller.
+    pub sleep: f64,
+    /// halted is set once the ><> has executed ";". Stepping a halted VM does nothing.
+    pub halted: bool,
+    /// error says what smelled fishy, if the ><> failed. It stays on the failing cell, so stepping again fails
+    /// again.
+    pub error: Option<String>,
+    /// steps is how many instructions were executed, counting the final ";" but not one which failed.
+    pub steps: u32,
+}
+
+/// StarfishVM runs a *><> script from JavaScript. "F" can't use any files, and input is given with
+/// StarfishVM.injectInput.
+#[wasm_bindgen]
+pub struct StarfishVM {
+    codebox: CodeBox,
+    input: Sender<u8>,
+    halted: bool,
+}
+
+#[wasm_bindgen]
+impl StarfishVM {
+    /// new returns a VM for source, starting with the values in stack, written as the command line's --stack is.
+    #[wasm_bindgen(constructor)]
+    pub fn new(source: &str, stack: Option<String>) -> Result<StarfishVM, JsError> {
+        let stack = match stack {
+            None => Stack::new(None),
+            Some(stack) => Stack::from_string(&stack).map_err(|e| JsError::new(&e.to_string()))?,
+        };
+        let (io, input) = ChannelIo::new();
+        let codebox = CodeBox::builder(source).stack(stack).io(io).build();
+        Ok(StarfishVM {
+            codebox,
+            input,
+            halted: false,
+        })
+    }
+
+    /// step executes up to n instructions, stopping early if the ><> halts, fails or sleeps.
+    pub fn step(&mut self, n: u32) -> Report {
+        self.swim_while(|report| report.steps < n)
+    }
+
+    /// runFor executes instructions for up to ms milliseconds, stopping early if the ><> halts, fails or sleeps.
+    #[wasm_bindgen(js_name = runFor)]
+    pub fn run_for(&mut self, ms: f64) -> Report {
+        let start = js_sys::Date::now();
+        self.swim_while(|_| js_sys::Date::now() - start < ms)
+    }
+
+    /// injectInput queues input for "i" to read.
+    #[wasm_bindgen(js_name = injectInput)]
+    pub fn inject_input(&self, input: &str) {
+        for &b in input.as_bytes() {
+            // The receiver lives in the CodeBox, so this can't fail.
+            _ = self.input.send(b);
+        }
+    }
+
+    /// codebox returns every cell within the codebox's bounds as an array of rows of numbers.
+    pub fn codebox(&self) -> Result<JsValue, JsValue> {
+        let rows: Vec<Vec<f64>> = self
+            .codebox
+            .code_box()
+            .iter()
+            .map(|row| row.iter().map(|v| v.to_f64()).collect())
+            .collect();
+        Ok(serde_wasm_bindgen::to_value(&rows)?)
+    }
+
+    /// stacks returns every stack as an array of numbers, bottom first and with the top of each stack last.
+    pub fn stacks(&self) -> Result<JsValue, JsValue> {
+        let stacks: Vec<Vec<f64>> = self
+            .codebox
+            .stacks()
+            .iter()
+            .map(|stack| stack.s.iter().map(|v| v.to_f64()).collect())
+            .collect();
+        Ok(serde_wasm_bindgen::to_value(&stacks)?)
+    }
+
+    /// position returns the x/y coordinates of the ><> as an array.
+    pub fn position(&self) -> Result<JsValue, JsValue> {
+        let (x, y) = self.codebox.position();
+        Ok(serde_wasm_bindgen::to_value(&[x as f64, y as f64])?)
+    }
+
+    /// snapshot returns the ><>'s complete state as JSON, as CodeBox.Snapshot does.
+    pub fn snapshot(&self) -> String {
+        self.codebox.snapshot().to_json()
+    }
+}
+
+impl StarfishVM {
+    /// swim_while swims while more returns true for the report so far, stopping early if the ><> halts, fails or
+    /// sleeps.
+    fn swim_while(&mut self, mut more: impl FnMut(&Report) -> bool) -> Report {
+        let mut report = Report {
+            halted: self.halted,
+            ..Report::default()
+        };
+        while !report.halted && more(&report) {
+            match self.codebox.swim() {
+                Ok(step) => {
+                    report.steps += 1;
+                    match step {
+                        Step::Continue => {}
+                        Step::Output(s) => report.output += &s,
+                        Step::Sleep(ms) => {
+                            report.sleep = ms;
+                            break;
+                        }
+                        Step::Halt => {
+                            self.halted = true;
+                            report.halted = true;
+                        }
+                    }
+                }
+                Err(e) => {
+                    report.error = Some(e.to_string());
+                    break;
+                }
+            }
+        }
+        report
+    }
+}
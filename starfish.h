@@ -0,0 +1,146 @@
+/* The C ABI for the *><> interpreter. Build it with: cargo build --release --features capi */
+
+#ifndef STARFISH_H
+#define STARFISH_H
+
+/* Generated by cbindgen from src/capi.rs. Don't edit it by hand. */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+// The call succeeded. After stepping or running, the ><> hasn't halted yet.
+#define STARFISH_OK 0
+
+// The ><> has halted. Stepping or running it again does nothing.
+#define STARFISH_HALTED 1
+
+// A required pointer was NULL.
+#define STARFISH_ERR_NULL -1
+
+// An argument was invalid, like a script which isn't UTF-8 or an initial stack which can't be parsed.
+#define STARFISH_ERR_ARGUMENT -2
+
+// Something smelled fishy: the ><> failed, and stays on the failing cell.
+#define STARFISH_ERR_SCRIPT -3
+
+// The interpreter panicked. The VM shouldn't be used again, other than to free it.
+#define STARFISH_ERR_PANIC -4
+
+// StarfishVm is the opaque starfish_vm handle. Its input and output are kept in memory, "S" doesn't really
+// sleep, and "F" can't use any files.
+typedef struct starfish_vm starfish_vm;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+// starfish_vm_new creates a VM for the NUL-terminated script source, starting with the values in stack, written
+// as the command line's --stack is. stack may be NULL for an empty stack. On success *vm is set to the new VM,
+// which must be freed with starfish_vm_free.
+//
+// # Safety
+//
+// source and stack, unless NULL, must be NUL-terminated strings, and vm must point to writable memory.
+int starfish_vm_new(const char *source,
+                    const char *stack,
+                    struct starfish_vm **vm);
+
+// starfish_vm_free frees vm. It does nothing if vm is NULL.
+//
+// # Safety
+//
+// vm must be NULL or a VM from starfish_vm_new which hasn't already been freed.
+void starfish_vm_free(struct starfish_vm *vm);
+
+// starfish_vm_step executes up to steps instructions, returning STARFISH_HALTED if the ><> halts,
+// STARFISH_ERR_SCRIPT if it fails, or STARFISH_OK.
+//
+// # Safety
+//
+// vm must be NULL or a VM from starfish_vm_new.
+int starfish_vm_step(struct starfish_vm *vm, uint64_t steps);
+
+// starfish_vm_run runs until the ><> halts or fails, or it has executed max_steps instructions or run for
+// max_ms milliseconds, where 0 means no limit. It returns what starfish_vm_step does.
+//
+// # Safety
+//
+// vm must be NULL or a VM from starfish_vm_new.
+int starfish_vm_run(struct starfish_vm *vm,
+                    uint64_t max_steps,
+                    uint64_t max_ms);
+
+// starfish_vm_feed_input queues len bytes of input for "i" to read.
+//
+// # Safety
+//
+// vm must be NULL or a VM from starfish_vm_new, and input must point to len readable bytes.
+int starfish_vm_feed_input(struct starfish_vm *vm, const uint8_t *input, size_t len);
+
+// starfish_vm_output_len returns how many bytes of output are waiting to be read, or 0 if vm is NULL.
+//
+// # Safety
+//
+// vm must be NULL or a VM from starfish_vm_new.
+size_t starfish_vm_output_len(const struct starfish_vm *vm);
+
+// starfish_vm_read_output moves up to cap bytes of the ><>'s output into buf, returning how many were moved.
+// Anything which doesn't fit is kept for the next call.
+//
+// # Safety
+//
+// vm must be NULL or a VM from starfish_vm_new, and buf must point to cap writable bytes.
+size_t starfish_vm_read_output(struct starfish_vm *vm,
+                               uint8_t *buf,
+                               size_t cap);
+
+// starfish_vm_stack_count returns how many stacks there are, or 0 if vm is NULL.
+//
+// # Safety
+//
+// vm must be NULL or a VM from starfish_vm_new.
+size_t starfish_vm_stack_count(const struct starfish_vm *vm);
+
+// starfish_vm_current_stack returns the index of the stack instructions operate on, counting from the bottom.
+//
+// # Safety
+//
+// vm must be NULL or a VM from starfish_vm_new.
+size_t starfish_vm_current_stack(const struct starfish_vm *vm);
+
+// starfish_vm_stack copies up to cap values of the stack at index into values, bottom first, and sets *len to
+// how many values the stack holds. values may be NULL when cap is 0, to find out how big the stack is.
+//
+// # Safety
+//
+// vm must be NULL or a VM from starfish_vm_new, values must point to cap writable doubles, and len must point to
+// a writable size_t.
+int starfish_vm_stack(const struct starfish_vm *vm,
+                      size_t index,
+                      double *values,
+                      size_t cap,
+                      size_t *len);
+
+// starfish_vm_position sets *x and *y to the coordinates of the ><>.
+//
+// # Safety
+//
+// vm must be NULL or a VM from starfish_vm_new, and x and y must point to writable int64_ts.
+int starfish_vm_position(const struct starfish_vm *vm, int64_t *x, int64_t *y);
+
+// starfish_vm_error returns the message for the last STARFISH_ERR_SCRIPT, or NULL if there hasn't been one. It's
+// valid until the next call which fails, or until vm is freed.
+//
+// # Safety
+//
+// vm must be NULL or a VM from starfish_vm_new.
+const char *starfish_vm_error(const struct starfish_vm *vm);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* STARFISH_H */
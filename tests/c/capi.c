@@ -0,0 +1,88 @@
+/* Exercises the C ABI declared in starfish.h. tests/capi.rs compiles and runs it against the cdylib. */
+#include <stdio.h>
+#include <string.h>
+
+#include "starfish.h"
+
+#define CHECK(cond)                                                        \
+    do {                                                                   \
+        if (!(cond)) {                                                     \
+            fprintf(stderr, "%s:%d: check failed: %s\n", __FILE__,         \
+                    __LINE__, #cond);                                      \
+            return 1;                                                      \
+        }                                                                  \
+    } while (0)
+
+static int test_hello(void) {
+    starfish_vm *vm = NULL;
+    CHECK(starfish_vm_new("\"olleh\"ooooo;", NULL, &vm) == STARFISH_OK);
+
+    CHECK(starfish_vm_step(vm, 3) == STARFISH_OK);
+    int64_t x, y;
+    CHECK(starfish_vm_position(vm, &x, &y) == STARFISH_OK);
+    CHECK(x == 3 && y == 0);
+    double values[4];
+    size_t len;
+    CHECK(starfish_vm_stack(vm, 0, values, 4, &len) == STARFISH_OK);
+    CHECK(len == 2 && values[0] == 'o' && values[1] == 'l');
+
+    CHECK(starfish_vm_run(vm, 0, 0) == STARFISH_HALTED);
+    CHECK(starfish_vm_output_len(vm) == 5);
+    char out[8] = {0};
+    CHECK(starfish_vm_read_output(vm, (uint8_t *)out, 2) == 2);
+    CHECK(starfish_vm_read_output(vm, (uint8_t *)out + 2, sizeof out - 3) == 3);
+    CHECK(strcmp(out, "hello") == 0);
+    CHECK(starfish_vm_step(vm, 1) == STARFISH_HALTED);
+    CHECK(starfish_vm_error(vm) == NULL);
+
+    starfish_vm_free(vm);
+    return 0;
+}
+
+static int test_input_and_stacks(void) {
+    starfish_vm *vm = NULL;
+    CHECK(starfish_vm_new("i+n1[;", "1 2", &vm) == STARFISH_OK);
+    CHECK(starfish_vm_feed_input(vm, (const uint8_t *)"A", 1) == STARFISH_OK);
+    CHECK(starfish_vm_run(vm, 100, 1000) == STARFISH_HALTED);
+
+    char out[8] = {0};
+    CHECK(starfish_vm_read_output(vm, (uint8_t *)out, sizeof out - 1) == 2);
+    CHECK(strcmp(out, "67") == 0);
+    CHECK(starfish_vm_stack_count(vm) == 2);
+    CHECK(starfish_vm_current_stack(vm) == 1);
+    size_t len;
+    CHECK(starfish_vm_stack(vm, 0, NULL, 0, &len) == STARFISH_OK);
+    CHECK(len == 0);
+    double value;
+    CHECK(starfish_vm_stack(vm, 1, &value, 1, &len) == STARFISH_OK);
+    CHECK(len == 1 && value == 1);
+    CHECK(starfish_vm_stack(vm, 2, NULL, 0, &len) == STARFISH_ERR_ARGUMENT);
+
+    starfish_vm_free(vm);
+    return 0;
+}
+
+static int test_errors(void) {
+    starfish_vm *vm = NULL;
+    CHECK(starfish_vm_new(NULL, NULL, &vm) == STARFISH_ERR_NULL);
+    CHECK(starfish_vm_new(";", "not a stack", &vm) == STARFISH_ERR_ARGUMENT);
+    CHECK(starfish_vm_step(NULL, 1) == STARFISH_ERR_NULL);
+
+    CHECK(starfish_vm_new("1n0,;", NULL, &vm) == STARFISH_OK);
+    CHECK(starfish_vm_run(vm, 0, 0) == STARFISH_ERR_SCRIPT);
+    CHECK(starfish_vm_error(vm) != NULL);
+    CHECK(starfish_vm_output_len(vm) == 1);
+    CHECK(starfish_vm_step(vm, 1) == STARFISH_ERR_SCRIPT);
+
+    starfish_vm_free(vm);
+    starfish_vm_free(NULL);
+    return 0;
+}
+
+int main(void) {
+    if (test_hello() || test_input_and_stacks() || test_errors()) {
+        return 1;
+    }
+    puts("ok");
+    return 0;
+}
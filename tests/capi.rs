@@ -0,0 +1,49 @@
+#![cfg(feature = "capi")]
+
+use std::path::PathBuf;
+use std::process::Command;
+
+/// The C test program is compiled with the system's C compiler against starfish.h and the cdylib. The cdylib is
+/// built with the capi feature first, in a target directory of its own, so the test doesn't depend on what else has
+/// been built and doesn't wait on the build running it.
+#[test]
+fn c_program() {
+    let root = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    let exe = std::env::current_exe().unwrap();
+    let target_dir = exe.ancestors().nth(3).unwrap().join("capi");
+    let status = Command::new(std::env::var("CARGO").unwrap_or_else(|_| String::from("cargo")))
+        .args(["build", "--lib", "--features", "capi", "--manifest-path"])
+        .arg(root.join("Cargo.toml"))
+        .arg("--target-dir")
+        .arg(&target_dir)
+        .status()
+        .expect("couldn't run cargo");
+    assert!(status.success());
+    let lib_dir = target_dir.join("debug");
+    let program = lib_dir.join("capi_test");
+
+    let status = Command::new(std::env::var("CC").unwrap_or_else(|_| String::from("cc")))
+        .arg(root.join("tests/c/capi.c"))
+        .arg("-I")
+        .arg(&root)
+        .arg("-L")
+        .arg(&lib_dir)
+        .arg("-lstarfish")
+        .arg("-o")
+        .arg(&program)
+        .status()
+        .expect("couldn't run the C compiler");
+    assert!(status.success());
+
+    let output = Command::new(&program)
+        .env("LD_LIBRARY_PATH", &lib_dir)
+        .env("DYLD_LIBRARY_PATH", &lib_dir)
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "ok\n");
+}
@@ -0,0 +1,23 @@
+use chrono::NaiveTime;
+use starfish::{Clock, CodeBox, Limits, MemoryIo};
+
+#[test]
+fn clock_script_under_a_virtual_clock() {
+    let script = include_str!("../scripts/clock.sf");
+    let mut codebox = CodeBox::builder(script)
+        .io(MemoryIo::default())
+        .clock(Clock::Virtual(NaiveTime::from_hms_opt(23, 59, 58).unwrap()))
+        .build();
+    let report = codebox.run(Limits {
+        max_steps: Some(1500),
+        ..Limits::default()
+    });
+    // Each "1S" moves the clock on a tenth of a second, and it wraps around at midnight.
+    let expected = format!(
+        "{}{}{}0:0:0 ",
+        "23:59:58   \r".repeat(10),
+        "23:59:59   \r".repeat(10),
+        "0:0:0   \r".repeat(9)
+    );
+    assert_eq!(String::from_utf8(report.output).unwrap(), expected);
+}
@@ -0,0 +1,251 @@
+#![cfg(feature = "cli")]
+
+use serde_json::{json, Value};
+use std::fs;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+
+/// Client drives "starfish dap" over its stdin and stdout, as an editor would.
+struct Client {
+    child: Child,
+    stdin: ChildStdin,
+    stdout: BufReader<ChildStdout>,
+    seq: u64,
+    events: Vec<Value>,
+}
+
+impl Client {
+    /// start runs "starfish dap" in dir and initializes it.
+    fn start(dir: &Path) -> Client {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_starfish"))
+            .arg("dap")
+            .current_dir(dir)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let mut client = Client {
+            stdin: child.stdin.take().unwrap(),
+            stdout: BufReader::new(child.stdout.take().unwrap()),
+            child,
+            seq: 0,
+            events: Vec::new(),
+        };
+        let response = client.request("initialize", json!({ "adapterID": "starfish" }));
+        assert_eq!(response["body"]["supportsConfigurationDoneRequest"], true);
+        client.event("initialized");
+        client
+    }
+
+    fn receive(&mut self) -> Value {
+        let mut length = 0;
+        loop {
+            let mut line = String::new();
+            assert!(
+                self.stdout.read_line(&mut line).unwrap() > 0,
+                "adapter closed stdout"
+            );
+            let line = line.trim_end();
+            if line.is_empty() {
+                break;
+            }
+            if let Some(n) = line.strip_prefix("Content-Length: ") {
+                length = n.parse().unwrap();
+            }
+        }
+        let mut body = vec![0; length];
+        self.stdout.read_exact(&mut body).unwrap();
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    /// request sends a request and returns its response, keeping any events which arrive first.
+    fn request(&mut self, command: &str, arguments: Value) -> Value {
+        self.seq += 1;
+        let seq = self.seq;
+        let body =
+            json!({ "seq": seq, "type": "request", "command": command, "arguments": arguments })
+                .to_string();
+        write!(self.stdin, "Content-Length: {}\r\n\r\n{}", body.len(), body).unwrap();
+        self.stdin.flush().unwrap();
+        loop {
+            let message = self.receive();
+            if message["type"] == "response" && message["request_seq"] == seq {
+                assert_eq!(message["success"], true, "{}", message);
+                return message;
+            }
+            self.events.push(message);
+        }
+    }
+
+    /// event returns the next event named event, keeping any other events which arrive first.
+    fn event(&mut self, event: &str) -> Value {
+        if let Some(i) = self.events.iter().position(|e| e["event"] == event) {
+            return self.events.remove(i);
+        }
+        loop {
+            let message = self.receive();
+            if message["event"] == event {
+                return message;
+            }
+            self.events.push(message);
+        }
+    }
+
+    /// stopped waits for the ><> to stop, and returns why with the top stack frame.
+    fn stopped(&mut self) -> (Value, Value) {
+        let reason = self.event("stopped")["body"]["reason"].clone();
+        let trace = self.request("stackTrace", json!({ "threadId": 1 }));
+        (reason, trace["body"]["stackFrames"][0].clone())
+    }
+
+    /// variables returns the names and values of the variables under reference.
+    fn variables(&mut self, reference: &Value) -> Vec<(String, String)> {
+        let response = self.request("variables", json!({ "variablesReference": reference }));
+        response["body"]["variables"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|v| {
+                (
+                    v["name"].as_str().unwrap().to_string(),
+                    v["value"].as_str().unwrap().to_string(),
+                )
+            })
+            .collect()
+    }
+
+    /// finish waits for the script to end, returning its output and exit code, then disconnects.
+    fn finish(mut self) -> (String, Value) {
+        let code = self.event("exited")["body"]["exitCode"].clone();
+        self.event("terminated");
+        let output = self
+            .events
+            .iter()
+            .filter(|e| e["event"] == "output" && e["body"]["category"] == "stdout")
+            .map(|e| e["body"]["output"].as_str().unwrap())
+            .collect();
+        self.request("disconnect", json!({}));
+        assert!(self.child.wait().unwrap().success());
+        (output, code)
+    }
+}
+
+/// temp_dir returns an empty directory for a test to run in.
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("starfish-dap-{}-{}", name, std::process::id()));
+    _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+#[test]
+fn dap_hello() {
+    // hello.sf outputs hello.txt from the directory it's run in.
+    let dir = temp_dir("hello");
+    fs::write(dir.join("hello.txt"), "hello\n").unwrap();
+    let program = Path::new(env!("CARGO_MANIFEST_DIR")).join("scripts/hello.sf");
+    let program = program.to_str().unwrap();
+    let mut client = Client::start(&dir);
+
+    client.request("launch", json!({ "program": program, "stopOnEntry": true }));
+    // Stop on the "i" which reads the file.
+    let response = client.request(
+        "setBreakpoints",
+        json!({ "source": { "path": program }, "breakpoints": [{ "line": 2, "column": 13 }] }),
+    );
+    assert_eq!(response["body"]["breakpoints"][0]["verified"], true);
+    client.request("configurationDone", json!({}));
+
+    let (reason, frame) = client.stopped();
+    assert_eq!(reason, "entry");
+    assert_eq!((&frame["line"], &frame["column"]), (&json!(1), &json!(1)));
+    assert_eq!(frame["source"]["path"], program);
+    let threads = client.request("threads", json!({}));
+    assert_eq!(threads["body"]["threads"][0]["id"], 1);
+
+    client.request("next", json!({ "threadId": 1 }));
+    let (reason, frame) = client.stopped();
+    assert_eq!(reason, "step");
+    assert_eq!(frame["column"], 2);
+
+    client.request("continue", json!({ "threadId": 1 }));
+    let (reason, frame) = client.stopped();
+    assert_eq!(reason, "breakpoint");
+    assert_eq!((&frame["line"], &frame["column"]), (&json!(2), &json!(13)));
+
+    client.request("stepIn", json!({ "threadId": 1 }));
+    client.stopped();
+    let scopes = client.request("scopes", json!({ "frameId": 0 }));
+    let stacks = &scopes["body"]["scopes"][0];
+    assert_eq!(stacks["name"], "Stacks");
+    let response = client.request(
+        "variables",
+        json!({ "variablesReference": stacks["variablesReference"] }),
+    );
+    let stack = &response["body"]["variables"][0];
+    assert_eq!(stack["name"], "stack 0 (current)");
+    assert_eq!(
+        client.variables(&stack["variablesReference"]),
+        [
+            (String::from("0"), String::from("104")),
+            (String::from("register"), String::from("empty")),
+        ]
+    );
+
+    client.request(
+        "setBreakpoints",
+        json!({ "source": { "path": program }, "breakpoints": [] }),
+    );
+    client.request("continue", json!({ "threadId": 1 }));
+    assert_eq!(client.finish(), (String::from("hello\n"), json!(0)));
+}
+
+#[test]
+fn dap_calls() {
+    let dir = temp_dir("calls");
+    let program = dir.join("calls.sf");
+    fs::write(&program, "11C;\n  R\n").unwrap();
+    let program = program.to_str().unwrap();
+    let mut client = Client::start(&dir);
+
+    client.request(
+        "setBreakpoints",
+        json!({ "source": { "path": program }, "breakpoints": [{ "line": 2, "column": 3 }] }),
+    );
+    client.request("launch", json!({ "program": program }));
+    client.request("configurationDone", json!({}));
+
+    // The "R" is inside the call made by the "C" at 2, 0.
+    let (reason, _) = client.stopped();
+    assert_eq!(reason, "breakpoint");
+    let trace = client.request("stackTrace", json!({ "threadId": 1 }));
+    let frames = trace["body"]["stackFrames"].as_array().unwrap();
+    let frames: Vec<_> = frames
+        .iter()
+        .map(|f| (f["name"].clone(), f["line"].clone(), f["column"].clone()))
+        .collect();
+    assert_eq!(
+        frames,
+        [
+            (json!("call from (2, 0)"), json!(2), json!(3)),
+            (json!("main"), json!(1), json!(3)),
+        ]
+    );
+    let stacks = client.variables(&json!(2));
+    assert_eq!(
+        stacks,
+        [
+            (String::from("stack 0"), String::from("[2.0, 0.0]")),
+            (String::from("stack 1 (current)"), String::from("[]")),
+        ]
+    );
+
+    client.request("stepOut", json!({ "threadId": 1 }));
+    let (reason, frame) = client.stopped();
+    assert_eq!(reason, "step");
+    assert_eq!((&frame["line"], &frame["column"]), (&json!(1), &json!(4)));
+
+    client.request("continue", json!({ "threadId": 1 }));
+    assert_eq!(client.finish(), (String::new(), json!(0)));
+}
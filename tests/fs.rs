@@ -0,0 +1,54 @@
+#![cfg(unix)]
+
+use std::fs;
+use std::io::{self, Read};
+use std::os::unix::fs::symlink;
+use std::path::PathBuf;
+
+use starfish::{FileSystem, RootFs};
+
+/// scratch returns an empty directory named for test, holding a root for a RootFs and a directory outside it.
+fn scratch(test: &str) -> (PathBuf, PathBuf) {
+    let dir = std::env::temp_dir().join(format!("starfish-{}-{}", test, std::process::id()));
+    _ = fs::remove_dir_all(&dir);
+    let (root, outside) = (dir.join("root"), dir.join("outside"));
+    fs::create_dir_all(&root).unwrap();
+    fs::create_dir_all(&outside).unwrap();
+    (root, outside)
+}
+
+#[test]
+fn root_fs_stays_inside_the_root() {
+    let (root, outside) = scratch("inside");
+    fs::write(outside.join("secret"), "secret").unwrap();
+    symlink(outside.join("secret"), root.join("link")).unwrap();
+    let mut root_fs = RootFs::new(&root).unwrap();
+
+    root_fs.write("/a", b"fish").unwrap();
+    let mut contents = String::new();
+    root_fs
+        .open("a")
+        .unwrap()
+        .read_to_string(&mut contents)
+        .unwrap();
+    assert_eq!(contents, "fish");
+
+    for path in ["../outside/secret", "link"] {
+        let error = root_fs.open(path).err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::PermissionDenied);
+    }
+    _ = fs::remove_dir_all(root.parent().unwrap());
+}
+
+#[test]
+fn root_fs_doesnt_follow_dangling_symlinks() {
+    let (root, outside) = scratch("dangling");
+    symlink(outside.join("planted"), root.join("dangling")).unwrap();
+    let mut root_fs = RootFs::new(&root).unwrap();
+
+    let error = root_fs.write("dangling", b"escaped").unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::PermissionDenied);
+    assert!(root_fs.open("dangling").is_err());
+    assert!(!outside.join("planted").exists());
+    _ = fs::remove_dir_all(root.parent().unwrap());
+}
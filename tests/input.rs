@@ -0,0 +1,33 @@
+use starfish::{CodeBox, Encoding, MemoryIo, StarfishError, Value};
+
+fn top(codebox: &CodeBox) -> Value {
+    codebox.stacks()[0].s.last().unwrap().clone()
+}
+
+#[test]
+fn characters_split_across_reads_wait_for_the_rest() {
+    let io = MemoryIo::new(&"é".as_bytes()[..1]);
+    let mut codebox = CodeBox::builder("ii;")
+        .encoding(Encoding::Codepoint)
+        .io(io.clone())
+        .build();
+    codebox.swim().unwrap();
+    assert_eq!(top(&codebox), Value::Float(-1.0));
+
+    io.push_input(&"é".as_bytes()[1..]);
+    codebox.swim().unwrap();
+    assert_eq!(top(&codebox), Value::Float('é' as u32 as f64));
+}
+
+#[test]
+fn bytes_which_arent_utf8_are_an_error() {
+    let io = MemoryIo::new(&[0xc3, b'a']);
+    let mut codebox = CodeBox::builder("i;")
+        .encoding(Encoding::Codepoint)
+        .io(io)
+        .build();
+    assert!(matches!(
+        codebox.swim(),
+        Err(StarfishError::InvalidInput(bytes, _)) if bytes == [0xc3, b'a']
+    ));
+}
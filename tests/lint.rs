@@ -0,0 +1,203 @@
+use starfish::{CodeBox, Diagnostic, Dialect, LintKind, MemoryIo, Severity};
+
+/// lint lints script in dialect.
+fn lint(script: &str, dialect: Dialect) -> Vec<Diagnostic> {
+    CodeBox::builder(script)
+        .dialect(dialect)
+        .io(MemoryIo::default())
+        .build()
+        .lint()
+}
+
+/// diagnostic builds the Diagnostic expected at x/y.
+fn diagnostic(
+    kind: LintKind,
+    severity: Severity,
+    x: i64,
+    y: i64,
+    length: u64,
+    message: &str,
+) -> Diagnostic {
+    Diagnostic {
+        kind,
+        severity,
+        x,
+        y,
+        length,
+        message: String::from(message),
+    }
+}
+
+const NO_HALT: &str = "no \";\" can be reached, so the ><> can't halt without an error";
+
+#[test]
+fn clean_script() {
+    assert_eq!(lint("\"hi\"oo;", Dialect::Starfish), vec![]);
+}
+
+#[test]
+fn unreachable_runs_of_cells() {
+    assert_eq!(
+        lint("1n;kk\n \"ab", Dialect::Starfish),
+        vec![
+            diagnostic(
+                LintKind::Unreachable,
+                Severity::Warning,
+                3,
+                0,
+                2,
+                "unreachable code"
+            ),
+            diagnostic(
+                LintKind::Unreachable,
+                Severity::Warning,
+                1,
+                1,
+                3,
+                "unreachable code"
+            ),
+        ]
+    );
+}
+
+#[test]
+fn unclosed_string() {
+    assert_eq!(
+        lint("\"ab\n;", Dialect::Starfish),
+        vec![
+            diagnostic(LintKind::NoHalt, Severity::Warning, 0, 0, 1, NO_HALT),
+            diagnostic(
+                LintKind::UnclosedString,
+                Severity::Warning,
+                0,
+                0,
+                1,
+                "the string opened by '\"' wraps around to it without being closed",
+            ),
+            diagnostic(
+                LintKind::Unreachable,
+                Severity::Warning,
+                0,
+                1,
+                1,
+                "unreachable code"
+            ),
+        ]
+    );
+}
+
+#[test]
+fn invalid_instruction() {
+    assert_eq!(
+        lint("1k;", Dialect::Starfish),
+        vec![
+            diagnostic(LintKind::NoHalt, Severity::Warning, 0, 0, 1, NO_HALT),
+            diagnostic(
+                LintKind::InvalidInstruction,
+                Severity::Error,
+                1,
+                0,
+                1,
+                "'k' isn't an instruction"
+            ),
+            diagnostic(
+                LintKind::Unreachable,
+                Severity::Warning,
+                2,
+                0,
+                1,
+                "unreachable code"
+            ),
+        ]
+    );
+}
+
+#[test]
+fn instruction_missing_from_the_dialect() {
+    assert_eq!(
+        lint("hn;", Dialect::Fish),
+        vec![
+            diagnostic(LintKind::NoHalt, Severity::Warning, 0, 0, 1, NO_HALT),
+            diagnostic(
+                LintKind::Dialect,
+                Severity::Error,
+                0,
+                0,
+                1,
+                "'h' is a *><> instruction, which the fish dialect doesn't have",
+            ),
+            diagnostic(
+                LintKind::Unreachable,
+                Severity::Warning,
+                1,
+                0,
+                2,
+                "unreachable code"
+            ),
+        ]
+    );
+    assert_eq!(
+        lint("hn;", Dialect::Starfish)
+            .iter()
+            .map(|d| d.kind)
+            .collect::<Vec<_>>(),
+        vec![LintKind::Nondeterministic]
+    );
+}
+
+#[test]
+fn constant_jump_out_of_bounds() {
+    assert_eq!(
+        lint("95.;", Dialect::Starfish),
+        vec![
+            diagnostic(LintKind::NoHalt, Severity::Warning, 0, 0, 1, NO_HALT),
+            diagnostic(
+                LintKind::JumpOutOfBounds,
+                Severity::Error,
+                2,
+                0,
+                1,
+                "jumps to (9, 5), which is outside of the codebox",
+            ),
+            diagnostic(
+                LintKind::Unreachable,
+                Severity::Warning,
+                3,
+                0,
+                1,
+                "unreachable code"
+            ),
+        ]
+    );
+}
+
+#[test]
+fn constant_jumps_are_followed() {
+    // The jump skips over the "k" straight to the ";".
+    assert_eq!(
+        lint("30.k;", Dialect::Starfish),
+        vec![diagnostic(
+            LintKind::Unreachable,
+            Severity::Warning,
+            3,
+            0,
+            1,
+            "unreachable code"
+        )]
+    );
+}
+
+#[test]
+fn nondeterministic_instruction() {
+    assert_eq!(
+        lint("x;", Dialect::Starfish),
+        vec![diagnostic(
+            LintKind::Nondeterministic,
+            Severity::Info,
+            0,
+            0,
+            1,
+            "'x' picks a random direction, so runs can differ",
+        )]
+    );
+}
@@ -0,0 +1,223 @@
+#![cfg(feature = "cli")]
+
+use serde_json::{json, Value};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::process::{ChildStdin, ChildStdout, Command, Stdio};
+
+/// Client drives "starfish lsp" over its stdin and stdout, as an editor would.
+struct Client {
+    stdin: ChildStdin,
+    stdout: BufReader<ChildStdout>,
+    next_id: u64,
+    notifications: Vec<Value>,
+}
+
+impl Client {
+    fn send(&mut self, message: Value) {
+        let body = message.to_string();
+        write!(self.stdin, "Content-Length: {}\r\n\r\n{}", body.len(), body).unwrap();
+        self.stdin.flush().unwrap();
+    }
+
+    fn receive(&mut self) -> Value {
+        let mut length = 0;
+        loop {
+            let mut line = String::new();
+            assert!(
+                self.stdout.read_line(&mut line).unwrap() > 0,
+                "server closed stdout"
+            );
+            let line = line.trim_end();
+            if line.is_empty() {
+                break;
+            }
+            if let Some(n) = line.strip_prefix("Content-Length: ") {
+                length = n.parse().unwrap();
+            }
+        }
+        let mut body = vec![0; length];
+        self.stdout.read_exact(&mut body).unwrap();
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    /// request sends a request and returns its response, keeping any notifications which arrive first.
+    fn request(&mut self, method: &str, params: Value) -> Value {
+        self.next_id += 1;
+        let id = self.next_id;
+        self.send(json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params }));
+        loop {
+            let message = self.receive();
+            if message["id"] == id {
+                return message;
+            }
+            self.notifications.push(message);
+        }
+    }
+
+    fn notify(&mut self, method: &str, params: Value) {
+        self.send(json!({ "jsonrpc": "2.0", "method": method, "params": params }));
+    }
+
+    /// notification returns the next notification with method.
+    fn notification(&mut self, method: &str) -> Value {
+        if let Some(i) = self
+            .notifications
+            .iter()
+            .position(|n| n["method"] == method)
+        {
+            return self.notifications.remove(i);
+        }
+        loop {
+            let message = self.receive();
+            if message["method"] == method {
+                return message;
+            }
+            self.notifications.push(message);
+        }
+    }
+}
+
+const URI: &str = "file:///test.sf";
+
+#[test]
+fn lsp_session() {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_starfish"))
+        .arg("lsp")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut client = Client {
+        stdin: child.stdin.take().unwrap(),
+        stdout: BufReader::new(child.stdout.take().unwrap()),
+        next_id: 0,
+        notifications: Vec::new(),
+    };
+
+    let response = client.request("initialize", json!({ "capabilities": {} }));
+    let capabilities = &response["result"]["capabilities"];
+    assert_eq!(capabilities["hoverProvider"], true);
+    assert_eq!(
+        capabilities["executeCommandProvider"]["commands"][0],
+        "starfish.run"
+    );
+    client.notify("initialized", json!({}));
+
+    // "k" isn't an instruction, and nothing after it can be reached.
+    client.notify(
+        "textDocument/didOpen",
+        json!({ "textDocument": { "uri": URI, "languageId": "starfish", "version": 1, "text": "1k;\n" } }),
+    );
+    let diagnostics = client.notification("textDocument/publishDiagnostics");
+    let diagnostics = diagnostics["params"]["diagnostics"].as_array().unwrap();
+    let invalid = diagnostics
+        .iter()
+        .find(|d| d["code"] == "invalid-instruction")
+        .expect("no diagnostic for k");
+    assert_eq!(invalid["severity"], 1);
+    assert_eq!(
+        invalid["range"]["start"],
+        json!({ "line": 0, "character": 1 })
+    );
+    assert!(diagnostics.iter().any(|d| d["code"] == "unreachable"));
+
+    client.notify(
+        "textDocument/didChange",
+        json!({
+            "textDocument": { "uri": URI, "version": 2 },
+            "contentChanges": [{ "text": "\"hi\"ooC;\n" }],
+        }),
+    );
+    let diagnostics = client.notification("textDocument/publishDiagnostics");
+    assert!(diagnostics["params"]["diagnostics"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .any(|d| d["code"] == "computed-jump"));
+
+    let hover = client.request(
+        "textDocument/hover",
+        json!({ "textDocument": { "uri": URI }, "position": { "line": 0, "character": 6 } }),
+    );
+    let hover = hover["result"]["contents"]["value"].as_str().unwrap();
+    assert!(hover.contains("`C`"), "{}", hover);
+    assert!(hover.contains("*><> only"), "{}", hover);
+    let hover = client.request(
+        "textDocument/hover",
+        json!({ "textDocument": { "uri": URI }, "position": { "line": 0, "character": 1 } }),
+    );
+    let hover = hover["result"]["contents"]["value"].as_str().unwrap();
+    assert!(hover.contains("In a string, this pushes 104"), "{}", hover);
+
+    // The string is one token, followed by two functions and two keywords merged as runs.
+    let tokens = client.request(
+        "textDocument/semanticTokens/full",
+        json!({ "textDocument": { "uri": URI } }),
+    );
+    assert_eq!(
+        tokens["result"]["data"],
+        json!([0, 0, 4, 0, 0, 0, 4, 2, 4, 0, 0, 2, 2, 2, 0])
+    );
+
+    // Strings are found by following the ><>, so one read downwards counts.
+    client.notify(
+        "textDocument/didChange",
+        json!({
+            "textDocument": { "uri": URI, "version": 3 },
+            "contentChanges": [{ "text": "v\n\"\nh\n\"\n;\n" }],
+        }),
+    );
+    client.notification("textDocument/publishDiagnostics");
+    let hover = client.request(
+        "textDocument/hover",
+        json!({ "textDocument": { "uri": URI }, "position": { "line": 2, "character": 0 } }),
+    );
+    let hover = hover["result"]["contents"]["value"].as_str().unwrap();
+    assert!(hover.contains("In a string, this pushes 104"), "{}", hover);
+
+    client.notify(
+        "textDocument/didChange",
+        json!({
+            "textDocument": { "uri": URI, "version": 4 },
+            "contentChanges": [{ "text": "\"!olleh\"ooooo;\n" }],
+        }),
+    );
+    let lenses = client.request(
+        "textDocument/codeLens",
+        json!({ "textDocument": { "uri": URI } }),
+    );
+    let command = &lenses["result"][0]["command"];
+    assert_eq!(command["command"], "starfish.run");
+    let run = client.request(
+        "workspace/executeCommand",
+        json!({ "command": command["command"], "arguments": command["arguments"] }),
+    );
+    assert_eq!(run["result"]["output"], "hello");
+    assert_eq!(run["result"]["exit"], "halted");
+
+    let unknown = client.request("textDocument/definition", json!({}));
+    assert_eq!(unknown["error"]["code"], -32601);
+
+    let shutdown = client.request("shutdown", Value::Null);
+    assert_eq!(shutdown["result"], Value::Null);
+    client.notify("exit", Value::Null);
+    assert!(child.wait().unwrap().success());
+}
+
+#[test]
+fn lsp_refuses_huge_messages() {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_starfish"))
+        .arg("lsp")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut stdin = child.stdin.take().unwrap();
+    write!(stdin, "Content-Length: 1000000000000\r\n\r\n{{}}").unwrap();
+    drop(stdin);
+    let output = child.wait_with_output().unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("over the limit"), "{}", stderr);
+}
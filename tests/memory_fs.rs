@@ -0,0 +1,20 @@
+use std::path::PathBuf;
+
+use starfish::{CodeBox, ExitReason, Limits, MemoryFs, MemoryIo, Value};
+
+#[test]
+fn memory_fs_keeps_what_f_writes() {
+    let files = MemoryFs::new();
+    let mut codebox = CodeBox::builder("\"out.txt\"7F\"hi\"2F\"out.txt\"7Fiii;")
+        .io(MemoryIo::default())
+        .file_system(files.clone())
+        .build();
+    let report = codebox.run(Limits::default());
+    assert!(matches!(report.exit, ExitReason::Halted));
+    assert_eq!(files.get("out.txt"), Some(b"hi".to_vec()));
+    assert_eq!(files.paths(), vec![PathBuf::from("out.txt")]);
+    assert_eq!(
+        report.stacks[0].s,
+        vec![Value::Float(104.0), Value::Float(105.0), Value::Float(-1.0)]
+    );
+}
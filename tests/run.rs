@@ -0,0 +1,43 @@
+use std::time::{Duration, Instant};
+
+use starfish::{CodeBox, ExitReason, Limits, MemoryIo, Step};
+
+#[test]
+fn output_limit_caps_what_is_written() {
+    let io = MemoryIo::default();
+    let mut codebox = CodeBox::builder("\"olleh\">o<").io(io.clone()).build();
+    let report = codebox.run(Limits {
+        max_output: Some(3),
+        ..Limits::default()
+    });
+    assert!(matches!(report.exit, ExitReason::OutputLimit));
+    assert_eq!(report.output, b"hel");
+    assert_eq!(io.take_output(), b"hel");
+
+    // The budget only applies during the run.
+    codebox.swim().unwrap();
+    codebox.swim().unwrap();
+    assert_eq!(io.take_output(), b"o");
+}
+
+#[test]
+fn sleeping_cant_overrun_the_time_limit() {
+    // "S" is asked to sleep for over five minutes.
+    let mut codebox = CodeBox::builder("fff**S;").build();
+    let start = Instant::now();
+    let report = codebox.run(Limits {
+        max_time: Some(Duration::from_millis(100)),
+        ..Limits::default()
+    });
+    assert!(matches!(report.exit, ExitReason::TimeLimit));
+    assert!(start.elapsed() < Duration::from_secs(2));
+}
+
+#[test]
+fn negative_sleeps_dont_sleep() {
+    let mut codebox = CodeBox::builder("01-S;").io(MemoryIo::default()).build();
+    for _ in 0..3 {
+        codebox.swim().unwrap();
+    }
+    assert_eq!(codebox.swim().unwrap(), Step::Sleep(0.0));
+}
@@ -0,0 +1,47 @@
+use std::fs;
+
+use chrono::NaiveTime;
+use starfish::{Clock, CodeBox, Limits, MemoryFs, MemoryIo, RunReport};
+
+/// run runs script for up to 100,000 steps, with or without the trace cache, and returns the report and where the
+/// ><> stopped.
+fn run(script: &str, trace_cache: bool) -> (RunReport, (i64, i64)) {
+    let mut codebox = CodeBox::builder(script)
+        .io(MemoryIo::new(b"fish\n"))
+        .file_system(MemoryFs::new())
+        .clock(Clock::Virtual(NaiveTime::from_hms_opt(12, 34, 56).unwrap()))
+        .seed(1)
+        .trace_cache(trace_cache)
+        .build();
+    let report = codebox.run(Limits {
+        max_steps: Some(100_000),
+        ..Limits::default()
+    });
+    (report, codebox.position())
+}
+
+#[test]
+fn traces_match_swimming_for_every_script() {
+    let mut paths: Vec<_> = fs::read_dir(concat!(env!("CARGO_MANIFEST_DIR"), "/scripts"))
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .collect();
+    paths.sort();
+    assert!(!paths.is_empty());
+    for path in paths {
+        let script = fs::read_to_string(&path).unwrap();
+        let (traced, traced_at) = run(&script, true);
+        let (swum, swum_at) = run(&script, false);
+        let name = path.display();
+        assert_eq!(traced.output, swum.output, "{}", name);
+        assert_eq!(traced.stacks, swum.stacks, "{}", name);
+        assert_eq!(traced.steps, swum.steps, "{}", name);
+        assert_eq!(traced_at, swum_at, "{}", name);
+        assert_eq!(
+            format!("{:?}", traced.exit),
+            format!("{:?}", swum.exit),
+            "{}",
+            name
+        );
+    }
+}
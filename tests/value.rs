@@ -0,0 +1,19 @@
+use std::cmp::Ordering;
+
+use starfish::Value;
+
+#[test]
+fn values_from_different_modes_compare_exactly() {
+    let big = Value::Int((1 << 53) + 1);
+    let float = Value::Float((1u64 << 53) as f64);
+    assert_eq!(big.partial_cmp(&float), Some(Ordering::Greater));
+    assert_eq!(float.partial_cmp(&big), Some(Ordering::Less));
+    assert_ne!(big, float);
+
+    assert_eq!(Value::Int(3), Value::Float(3.0));
+    assert!(Value::Int(3) > Value::Float(2.5));
+    assert!(Value::Float(f64::INFINITY) > Value::Int(i64::MAX));
+    assert!(Value::Int(i64::MIN) > Value::Float(f64::NEG_INFINITY));
+    assert_eq!(Value::Float(f64::NAN).partial_cmp(&Value::Int(0)), None);
+    assert_eq!(Value::Int(0).partial_cmp(&Value::Float(f64::NAN)), None);
+}
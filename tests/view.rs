@@ -0,0 +1,36 @@
+use starfish::{CodeBox, Limits, MemoryIo, Value, VIEW_SIZE};
+
+/// far_away runs a script which stores a 1 at (10000, 0), stretching the codebox's bounds far to the right.
+fn far_away() -> CodeBox {
+    let mut codebox = CodeBox::builder("1aa*:*0p;")
+        .io(MemoryIo::default())
+        .build();
+    codebox.run(Limits::default());
+    codebox
+}
+
+#[test]
+fn code_box_covers_the_whole_bounds() {
+    let codebox = far_away();
+    let rows = codebox.code_box();
+    assert_eq!(rows.len(), 1);
+    assert_eq!(rows[0].len(), 10001);
+    assert_eq!(rows[0][10000], Value::Float(1.0));
+}
+
+#[test]
+fn view_box_is_cut_to_the_view() {
+    let codebox = far_away();
+    assert_eq!(codebox.view(), ((0, 0), (VIEW_SIZE, 1)));
+    let rows = codebox.view_box();
+    assert_eq!(rows.len(), 1);
+    assert_eq!(rows[0].len(), VIEW_SIZE as usize);
+    assert_eq!(rows[0][..9], codebox.code_box()[0][..9]);
+}
+
+#[test]
+fn small_codeboxes_are_viewed_whole() {
+    let codebox = CodeBox::builder("1n;\n23").io(MemoryIo::default()).build();
+    assert_eq!(codebox.view(), ((0, 0), (3, 2)));
+    assert_eq!(codebox.view_box(), codebox.code_box());
+}
@@ -0,0 +1,62 @@
+//! Tests for the JavaScript API. They run under Node with no browser:
+//! wasm-pack test --node --features wasm
+#![cfg(all(target_arch = "wasm32", feature = "wasm"))]
+
+use starfish::StarfishVM;
+use wasm_bindgen::JsValue;
+use wasm_bindgen_test::*;
+
+fn numbers(value: JsValue) -> Vec<Vec<f64>> {
+    serde_wasm_bindgen::from_value(value).unwrap()
+}
+
+#[wasm_bindgen_test]
+fn steps_until_halted() {
+    let mut vm = StarfishVM::new("\"olleh\"ooooo;", None).unwrap();
+    let report = vm.step(3);
+    assert_eq!(report.steps, 3);
+    assert_eq!(numbers(vm.stacks().unwrap()), vec![vec![111.0, 108.0]]);
+    assert_eq!(
+        serde_wasm_bindgen::from_value::<Vec<f64>>(vm.position().unwrap()).unwrap(),
+        vec![3.0, 0.0]
+    );
+
+    let report = vm.step(100);
+    assert_eq!(report.output, "hello");
+    assert!(report.halted);
+    assert_eq!(report.error, None);
+    assert_eq!(vm.step(1).steps, 0);
+}
+
+#[wasm_bindgen_test]
+fn initial_stack_and_input() {
+    let mut vm = StarfishVM::new("i+n;", Some(String::from("1"))).unwrap();
+    vm.inject_input("A");
+    let report = vm.run_for(1000.0);
+    assert_eq!(report.output, "66");
+    assert!(report.halted);
+    assert!(StarfishVM::new(";", Some(String::from("not a stack"))).is_err());
+}
+
+#[wasm_bindgen_test]
+fn stops_to_sleep_and_on_errors() {
+    let mut vm = StarfishVM::new("5S1n0,", None).unwrap();
+    let report = vm.step(100);
+    assert_eq!(report.sleep, 500.0);
+    assert_eq!(report.steps, 2);
+
+    let report = vm.step(100);
+    assert_eq!(report.output, "1");
+    assert!(report.error.is_some());
+    assert!(!report.halted);
+}
+
+#[wasm_bindgen_test]
+fn codebox_and_snapshot() {
+    let vm = StarfishVM::new("ab\n;", None).unwrap();
+    assert_eq!(
+        numbers(vm.codebox().unwrap()),
+        vec![vec![97.0, 98.0], vec![59.0, 0.0]]
+    );
+    assert!(vm.snapshot().contains("\"stacks\""));
+}